@@ -1,93 +1,512 @@
-use bevy::prelude::*;
+use bevy::{asset::LoadState, audio::Volume, prelude::*, utils::HashMap};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::{combat, GameState};
+use crate::{
+    fadeout::ScreenFade,
+    input::{Action, Actions},
+    util,
+};
+
+use manifest::Manifest;
+
+mod manifest;
 
 pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, OverworldMusic::load)
-            .add_systems(OnEnter(GameState::Overworld), OverworldMusic::play)
-            .add_systems(OnExit(GameState::Overworld), OverworldMusic::pause)
-            .add_systems(OnEnter(GameState::Combat), CombatMusic::load)
-            .add_systems(Update, HitSfx::load.run_if(on_event::<combat::Event>()))
-            .add_systems(OnEnter(combat::State::Reward), RewardSfx::load)
-            .add_systems(OnExit(GameState::Combat), CombatMusic::despawn);
-    }
-}
-
-trait Track: Component + Sized {
-    fn load(
-        commands: Commands,
-        asset_server: Res<AssetServer>,
-        query: Query<&AudioSink, With<Self>>,
-    );
-
-    fn play(query: Query<&AudioSink, With<Self>>);
-
-    fn pause(query: Query<&AudioSink, With<Self>>);
-
-    fn despawn(commands: Commands, query: Query<(Entity, &AudioSink), With<Self>>);
-}
-
-macro_rules! audio_component {
-    ( $ty: ident, $asset_name: tt, $playback_setting: expr) => {
-        #[derive(Component)]
-        struct $ty;
-
-        impl Track for $ty {
-            fn load(
-                mut commands: Commands,
-                asset_server: Res<AssetServer>,
-                query: Query<&AudioSink, With<$ty>>,
-            ) {
-                use bevy::ecs::query::QuerySingleError;
-                match query.get_single() {
-                    Err(QuerySingleError::NoEntities(_)) => {
-                        use bevy::audio::Volume;
-                        commands.spawn((
-                            AudioBundle {
-                                source: asset_server.load($asset_name),
-                                settings: $playback_setting.with_volume(Volume::new_relative(0.6)),
-                            },
-                            $ty,
-                        ));
-                    }
-                    Ok(_) => {
-                        println!("Got single...")
-                    }
-                    Err(QuerySingleError::MultipleEntities(_)) => {
-                        unreachable!("we should only have one of each track loaded at a time")
-                    }
-                }
-            }
+        app.add_asset::<Manifest>()
+            .init_asset_loader::<manifest::Loader>()
+            .insert_resource(util::load_settings::<Mixer>(MIXER_PATH).clamped())
+            .init_resource::<MusicFade>()
+            .init_resource::<NowPlaying>()
+            .add_event::<MusicCommand>()
+            .add_event::<PlaySfx>()
+            .add_systems(PreStartup, load)
+            .add_systems(
+                Update,
+                (
+                    toggle_mute,
+                    (apply_mixer, save_mixer).run_if(resource_changed::<Mixer>()),
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    load_sfx,
+                    run_music_commands,
+                    report_missing_music,
+                    fade,
+                    play_sfx,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Lists every sound, by id.
+const MANIFEST_PATH: &str = "audio.manifest";
+
+/// Where the mixer's settings are saved between runs.
+const MIXER_PATH: &str = "settings/audio.ron";
+
+/// The volume of each kind of sound, as multiples of their tracks' own
+/// volumes. Changes are applied to sounds that are already playing.
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Mixer {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+    pub muted: bool,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Mixer {
+            master: 1.,
+            music: 1.,
+            sfx: 1.,
+            muted: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Music,
+    Sfx,
+}
+
+/// Puts a sound on one of the mixer's channels.
+#[derive(Component, Clone, Copy)]
+pub struct Mix {
+    pub channel: Channel,
+    /// The track's own volume, before the mixer's.
+    pub volume: f32,
+}
+
+impl Mixer {
+    /// Keeps the volumes between silent and full, e.g. when they've been
+    /// edited by hand.
+    fn clamped(mut self) -> Self {
+        self.master = self.master.clamp(0., 1.);
+        self.music = self.music.clamp(0., 1.);
+        self.sfx = self.sfx.clamp(0., 1.);
+        self
+    }
+
+    /// How loud a sound mixed with `mix` should play.
+    pub fn volume(&self, mix: Mix) -> f32 {
+        if self.muted {
+            return 0.;
+        }
+        let channel = match mix.channel {
+            Channel::Music => self.music,
+            Channel::Sfx => self.sfx,
+        };
+        self.master * channel * mix.volume
+    }
+
+    /// `settings` at the volume `mix` should play at. Use this when spawning
+    /// a sound, as the mixer only reaches its sink once it starts playing.
+    pub fn settings(&self, settings: PlaybackSettings, mix: Mix) -> PlaybackSettings {
+        settings.with_volume(Volume::new_relative(self.volume(mix)))
+    }
+}
+
+impl Mix {
+    pub fn music(volume: f32) -> Self {
+        Mix {
+            channel: Channel::Music,
+            volume,
+        }
+    }
+
+    pub fn sfx(volume: f32) -> Self {
+        Mix {
+            channel: Channel::Sfx,
+            volume,
+        }
+    }
+}
+
+fn toggle_mute(actions: Res<Actions>, mut mixer: ResMut<Mixer>) {
+    if actions.just_pressed(Action::Mute) {
+        mixer.muted = !mixer.muted;
+    }
+}
+
+/// Faded tracks have their volume set by `fade` instead.
+fn apply_mixer(mixer: Res<Mixer>, query: Query<(&AudioSink, &Mix), Without<Fader>>) {
+    for (sink, mix) in query.iter() {
+        sink.set_volume(mixer.volume(*mix));
+    }
+}
+
+fn save_mixer(mixer: Res<Mixer>) {
+    // the settings were only just read, so there's nothing new to save.
+    if mixer.is_added() {
+        return;
+    }
+    util::save_settings(MIXER_PATH, &*mixer);
+}
+
+/// How long music takes to fade in and out when it starts and stops, in
+/// seconds. While the screen is fading between states, music also fades along
+/// with it.
+#[derive(Resource)]
+pub struct MusicFade {
+    pub fade_in: f32,
+    pub fade_out: f32,
+}
+
+impl Default for MusicFade {
+    fn default() -> Self {
+        MusicFade {
+            fade_in: 0.5,
+            fade_out: 0.5,
+        }
+    }
+}
+
+/// Fades a music track's volume in or out, on top of the mixer's.
+#[derive(Component)]
+struct Fader {
+    level: f32,
+    target: f32,
+    /// What happens to the track once it's faded out.
+    on_silence: Option<Silence>,
+}
+
+#[derive(Clone, Copy)]
+enum Silence {
+    /// Pauses the track, so that it carries on from the same place when it's
+    /// played again.
+    Pause,
+    Despawn,
+}
+
+impl Fader {
+    /// Fades a track in from silence as it starts.
+    fn starting() -> Self {
+        Fader {
+            level: 0.,
+            target: 1.,
+            on_silence: None,
+        }
+    }
+
+    fn fade_in(&mut self) {
+        self.target = 1.;
+        self.on_silence = None;
+    }
+
+    fn fade_out(&mut self, then: Silence) {
+        self.target = 0.;
+        self.on_silence = Some(then);
+    }
 
-            fn play(query: Query<&AudioSink, With<$ty>>) {
-                if let Ok(sink) = query.get_single() {
-                    sink.play();
-                }
+    /// `settings` at the volume the track starts playing at.
+    fn settings(&self, mixer: &Mixer, settings: PlaybackSettings, mix: Mix) -> PlaybackSettings {
+        settings.with_volume(Volume::new_relative(mixer.volume(mix) * self.level))
+    }
+}
+
+fn fade(
+    mut commands: Commands,
+    mut query: Query<(Entity, &AudioSink, &Mix, &mut Fader)>,
+    screen_fades: Query<&ScreenFade>,
+    mixer: Res<Mixer>,
+    music_fade: Res<MusicFade>,
+    time: Res<Time>,
+) {
+    // how far the screen has faded out, so music can follow along.
+    let screen = screen_fades
+        .iter()
+        .map(|fade| 1. - fade.alpha)
+        .fold(1., f32::min);
+
+    for (entity, sink, mix, mut fader) in query.iter_mut() {
+        let step = |duration: f32| {
+            if duration > 0. {
+                time.delta_seconds() / duration
+            } else {
+                1.
             }
+        };
 
-            fn pause(query: Query<&AudioSink, With<$ty>>) {
-                if let Ok(sink) = query.get_single() {
-                    sink.pause();
-                }
+        let volume = if fader.target < fader.level {
+            // tracks fading out never get louder as the screen fades back in.
+            fader.level = (fader.level - step(music_fade.fade_out))
+                .max(fader.target)
+                .min(screen);
+            fader.level
+        } else {
+            fader.level = (fader.level + step(music_fade.fade_in)).min(fader.target);
+            fader.level * screen
+        };
+        sink.set_volume(mixer.volume(*mix) * volume);
+
+        if fader.level > 0. {
+            continue;
+        }
+        match fader.on_silence.take() {
+            Some(Silence::Pause) => sink.pause(),
+            Some(Silence::Despawn) => {
+                sink.stop();
+                commands.entity(entity).despawn_recursive();
             }
+            None => {}
+        }
+    }
+}
+
+/// The audio manifest, and the sound effects it lists, which are kept loaded
+/// so that they play straight away.
+#[derive(Resource)]
+struct Sounds {
+    manifest: Handle<Manifest>,
+    sfx: HashMap<String, Handle<AudioSource>>,
+}
+
+/// What music is playing, kept up to date as `MusicCommand`s are carried out.
+#[derive(Resource, Default)]
+pub struct NowPlaying {
+    /// The id of the track that's playing, or fading in.
+    pub music: Option<String>,
+    /// The id of the track that was playing before, which is kept paused so
+    /// that it carries on from the same place if it's played again, e.g. the
+    /// map's music after a fight.
+    pub paused: Option<String>,
+}
+
+/// Changes which music is playing, fading between tracks. Only the last
+/// command sent each frame is carried out.
+#[derive(Event, Clone)]
+pub enum MusicCommand {
+    /// Plays the track with this id from the audio manifest, pausing the
+    /// current one.
+    Play(String),
+    /// Pauses the current track, forgetting any older paused track.
+    Pause,
+    /// Plays the paused track again. The current track carries on if nothing
+    /// is paused.
+    Resume,
+    /// Stops the current track, without pausing it to be resumed.
+    Stop,
+}
 
-            fn despawn(mut commands: Commands, query: Query<(Entity, &AudioSink), With<$ty>>) {
-                if let Ok((entity, sink)) = query.get_single() {
-                    sink.stop();
-                    commands.entity(entity).despawn_recursive();
-                }
+/// Asks for the sound effect with this id in the audio manifest to be played.
+#[derive(Event)]
+pub struct PlaySfx(pub String);
+
+impl PlaySfx {
+    pub fn new(id: &str) -> Self {
+        PlaySfx(id.to_string())
+    }
+}
+
+/// A music track from the audio manifest.
+#[derive(Component)]
+pub struct MusicTrack {
+    pub id: String,
+}
+
+/// A sound effect from the audio manifest, which is despawned once it's played.
+#[derive(Component)]
+pub struct Sfx {
+    pub id: String,
+}
+
+fn load(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Sounds {
+        manifest: asset_server.load(MANIFEST_PATH),
+        sfx: HashMap::new(),
+    });
+}
+
+/// Loads every sound effect whenever the manifest is (re)loaded.
+fn load_sfx(
+    mut events: EventReader<AssetEvent<Manifest>>,
+    mut sounds: ResMut<Sounds>,
+    manifests: Res<Assets<Manifest>>,
+    asset_server: Res<AssetServer>,
+) {
+    let changed = events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            handle == &sounds.manifest
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    let Some(manifest) = manifests.get(&sounds.manifest).filter(|_| changed) else {
+        return;
+    };
+
+    sounds.sfx = manifest
+        .sfx
+        .iter()
+        .map(|(id, effect)| (id.clone(), asset_server.load(&effect.path)))
+        .collect();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_music_commands(
+    mut commands: Commands,
+    mut events: EventReader<MusicCommand>,
+    mut query: Query<(&MusicTrack, &mut Fader, Option<&AudioSink>)>,
+    mut now_playing: ResMut<NowPlaying>,
+    sounds: Res<Sounds>,
+    manifests: Res<Assets<Manifest>>,
+    asset_server: Res<AssetServer>,
+    mixer: Res<Mixer>,
+    mut waiting: Local<Option<MusicCommand>>,
+) {
+    // commands are read straight away, since unread events are dropped after
+    // a couple of frames, but wait until there's a manifest to look their
+    // tracks up in.
+    if let Some(command) = events.iter().last() {
+        *waiting = Some(command.clone());
+    }
+    let Some(manifest) = manifests.get(&sounds.manifest) else {
+        return;
+    };
+    let Some(command) = waiting.take() else {
+        return;
+    };
+
+    let NowPlaying { music, paused } = std::mem::take(&mut *now_playing);
+    let play = match &command {
+        MusicCommand::Play(id) => Some(id.clone()),
+        MusicCommand::Resume => paused.clone().or_else(|| music.clone()),
+        MusicCommand::Pause | MusicCommand::Stop => None,
+    };
+    if play.is_some() && play == music {
+        *now_playing = NowPlaying { music, paused };
+        return;
+    }
+
+    // only the most recent track is kept around to be resumed.
+    let paused = match &command {
+        MusicCommand::Stop => paused,
+        MusicCommand::Pause => music,
+        MusicCommand::Play(_) | MusicCommand::Resume => music.or(paused),
+    }
+    .filter(|id| Some(id) != play.as_ref());
+    *now_playing = NowPlaying {
+        music: play,
+        paused,
+    };
+
+    let mut found = false;
+    for (track, mut fader, sink) in query.iter_mut() {
+        if now_playing.music.as_ref() == Some(&track.id) {
+            found = true;
+            if let Some(sink) = sink {
+                sink.play();
             }
+            fader.fade_in();
+        } else if now_playing.paused.as_ref() == Some(&track.id) {
+            if fader.target > 0. {
+                fader.fade_out(Silence::Pause);
+            }
+        } else {
+            fader.fade_out(Silence::Despawn);
         }
+    }
+
+    let Some(id) = now_playing.music.clone().filter(|_| !found) else {
+        return;
     };
+    let Some(track) = manifest.music.get(&id) else {
+        warn!("There's no music called \"{id}\" in {MANIFEST_PATH}");
+        now_playing.music = None;
+        return;
+    };
+
+    let mix = Mix::music(track.volume);
+    let fader = Fader::starting();
+    commands.spawn((
+        AudioBundle {
+            source: asset_server.load(&track.path),
+            settings: fader.settings(&mixer, PlaybackSettings::LOOP, mix),
+        },
+        Name::new("Music"),
+        MusicTrack { id },
+        mix,
+        fader,
+    ));
+}
+
+fn report_missing_music(
+    mut commands: Commands,
+    query: Query<(Entity, &MusicTrack, &Handle<AudioSource>)>,
+    mut now_playing: ResMut<NowPlaying>,
+    asset_server: Res<AssetServer>,
+) {
+    for (entity, track, handle) in query.iter() {
+        if asset_server.get_load_state(handle) == LoadState::Failed {
+            warn!(
+                "Couldn't load the music \"{}\", so it won't play. Check that its path in \
+                 {MANIFEST_PATH} is right",
+                track.id
+            );
+            commands.entity(entity).despawn_recursive();
+
+            if now_playing.music.as_ref() == Some(&track.id) {
+                now_playing.music = None;
+            }
+            if now_playing.paused.as_ref() == Some(&track.id) {
+                now_playing.paused = None;
+            }
+        }
+    }
 }
 
-audio_component!(OverworldMusic, "bip-bop.ogg", PlaybackSettings::LOOP);
+fn play_sfx(
+    mut commands: Commands,
+    mut events: EventReader<PlaySfx>,
+    playing_query: Query<&Sfx>,
+    sounds: Res<Sounds>,
+    manifests: Res<Assets<Manifest>>,
+    mixer: Res<Mixer>,
+) {
+    let Some(manifest) = manifests.get(&sounds.manifest) else {
+        return;
+    };
 
-audio_component!(CombatMusic, "ganxta.ogg", PlaybackSettings::LOOP);
+    let mut playing: HashMap<&str, usize> = HashMap::new();
+    for sfx in playing_query.iter() {
+        *playing.entry(&sfx.id).or_default() += 1;
+    }
 
-audio_component!(HitSfx, "hit.wav", PlaybackSettings::REMOVE);
+    let mut rng = rand::thread_rng();
+    for PlaySfx(id) in events.iter() {
+        let (Some(effect), Some(source)) = (manifest.sfx.get(id), sounds.sfx.get(id)) else {
+            warn!("There's no sound effect called \"{id}\" in {MANIFEST_PATH}");
+            continue;
+        };
+        let count = playing.entry(id).or_default();
+        if *count >= effect.limit {
+            continue;
+        }
+        *count += 1;
 
-audio_component!(RewardSfx, "reward.wav", PlaybackSettings::REMOVE);
+        let mut vary = |amount: f32| 1. + rng.gen_range(-amount..=amount);
+        let mix = Mix::sfx(effect.volume * vary(effect.volume_variation));
+        let speed = vary(effect.pitch_variation);
+        commands.spawn((
+            AudioBundle {
+                source: source.clone(),
+                settings: mixer
+                    .settings(PlaybackSettings::DESPAWN, mix)
+                    .with_speed(speed),
+            },
+            Name::new("Sfx"),
+            Sfx { id: id.clone() },
+            mix,
+        ));
+    }
+}
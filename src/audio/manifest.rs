@@ -0,0 +1,76 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::{TypePath, TypeUuid},
+    utils::{BoxedFuture, HashMap},
+};
+use serde::Deserialize;
+
+/// The contents of a `.manifest` file: every sound the game plays, by id.
+#[derive(TypeUuid, TypePath, Deserialize)]
+#[uuid = "5e2d7c4a-9b1f-4c3e-8a6d-2f0b7e9c1d84"]
+pub struct Manifest {
+    #[serde(default)]
+    pub music: HashMap<String, Track>,
+    #[serde(default)]
+    pub sfx: HashMap<String, Effect>,
+}
+
+/// A music track, which loops until something else is played.
+#[derive(Deserialize)]
+pub struct Track {
+    /// The track's path in the assets folder.
+    pub path: String,
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+}
+
+/// A sound effect, which plays once.
+#[derive(Deserialize)]
+pub struct Effect {
+    /// The sound's path in the assets folder.
+    pub path: String,
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+    /// How much each play's pitch can vary either way, as a fraction of it.
+    #[serde(default)]
+    pub pitch_variation: f32,
+    /// How much each play's volume can vary either way, as a fraction of it.
+    #[serde(default)]
+    pub volume_variation: f32,
+    /// The most copies of the sound that can play at once. Any more are
+    /// dropped, so that e.g. quick menu presses don't pile up.
+    #[serde(default = "Effect::default_limit")]
+    pub limit: usize,
+}
+
+/// How loud sounds play at full volume on the mixer, unless they say otherwise.
+fn default_volume() -> f32 {
+    0.6
+}
+
+impl Effect {
+    fn default_limit() -> usize {
+        1
+    }
+}
+
+#[derive(Default)]
+pub struct Loader;
+
+impl AssetLoader for Loader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let manifest: Manifest = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(manifest));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["manifest"]
+    }
+}
@@ -1,48 +1,67 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 
-use strum::EnumCount;
+use serde::Deserialize;
+use strum::IntoEnumIterator;
 
 use crate::{
-    ascii, fadeout,
+    ascii, audio, fadeout,
     graphics::{self, CharacterSheet},
+    input, menu,
     player::{self, Player},
-    GameState, RESOLUTION, TILE_SIZE,
+    story, GameState, RESOLUTION, TILE_SIZE,
 };
 
+mod ai;
+pub mod boss;
+pub mod damage;
+mod sequence;
+
 pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         app.register_type::<Stats>()
+            .add_asset::<ai::EnemyData>()
+            .init_asset_loader::<ai::Loader>()
+            .add_asset::<boss::BossData>()
+            .init_asset_loader::<boss::Loader>()
+            .add_systems(Startup, load_bestiary)
             .add_state::<State>()
             .add_event::<Event>()
-            .insert_resource(MenuSelection {
-                selected: MenuOption::Fight,
-            })
-            .insert_resource(AttackAnimation {
-                timer: Timer::from_seconds(0.7, TimerMode::Repeating),
-                flash_speed: 0.1,
-                shake: Shake {
-                    max_distance: 0.1,
-                    current_distance: 0.,
-                },
-            })
+            .init_resource::<Encounter>()
+            .init_resource::<sequence::Shake>()
             // camera
             .add_systems(Update, camera.run_if(in_state(GameState::Combat)))
             // ui
-            .add_systems(OnEnter(GameState::Combat), menu::spawn)
+            .add_plugins(menu::Plugin::<MenuOption>::default())
+            .add_systems(OnEnter(GameState::Combat), spawn_menu)
             .add_systems(OnEnter(GameState::Combat), spawn_player_health)
-            .add_systems(Update, menu::button::highlight_selected)
-            .add_systems(OnExit(GameState::Combat), menu::despawn)
+            .add_systems(Update, activate_menu.run_if(in_state(GameState::Combat)))
+            .add_systems(
+                Update,
+                update_health_text
+                    .after(damage_calculation)
+                    .after(enemy_turn)
+                    .run_if(in_state(GameState::Combat)),
+            )
+            .add_systems(OnExit(GameState::Combat), despawn_menu)
             .add_systems(OnExit(GameState::Combat), despawn_text)
             // player
             .add_systems(OnEnter(GameState::Combat), player_goes_first)
             .add_systems(OnEnter(GameState::Combat), spawn_player_health)
             .add_systems(Update, input.run_if(in_state(GameState::Combat)))
+            .add_systems(Update, expire_messages.run_if(in_state(GameState::Combat)))
             // enemy
             .add_systems(OnEnter(GameState::Combat), spawn_enemy)
             .add_systems(Update, enemy_turn.run_if(in_state(State::EnemyTurn)))
-            .add_systems(OnExit(GameState::Combat), despawn_enemy)
+            .add_systems(OnExit(GameState::Combat), (despawn_enemy, clear_encounter))
+            // status effects
+            .add_systems(OnEnter(State::PlayerTurn), tick_statuses::<Player>)
+            .add_systems(OnEnter(State::EnemyTurn), tick_statuses::<Enemy>)
+            .add_systems(OnExit(GameState::Combat), clear_statuses)
+            // defending
+            .add_systems(OnEnter(State::PlayerTurn), stop_guarding)
+            .add_systems(OnExit(GameState::Combat), stop_guarding)
             // damage calculation
             .add_systems(
                 Update,
@@ -55,196 +74,509 @@ impl bevy::prelude::Plugin for Plugin {
                     .after(input)
                     .run_if(in_state(GameState::Combat)),
             )
-            // attack effects
-            .add_systems(Update, attack_effects.run_if(in_state(State::PlayerAttack)))
-            .add_systems(Update, attack_effects.run_if(in_state(State::EnemyAttack)))
-            .add_systems(OnEnter(State::Reward), (despawn_enemy, reward))
-            .add_systems(Update, accept_reward.run_if(in_state(State::Reward)));
+            // attack animations
+            .add_systems(
+                Update,
+                (sequence::advance, sequence::move_particles)
+                    .after(damage_calculation)
+                    .run_if(in_state(GameState::Combat)),
+            )
+            .add_systems(OnExit(GameState::Combat), sequence::stop)
+            .add_systems(
+                Update,
+                advance_boss_phase
+                    .after(damage_calculation)
+                    .run_if(in_state(GameState::Combat)),
+            )
+            .add_systems(
+                OnEnter(State::Reward),
+                (despawn_enemy, reward, boss_defeated),
+            )
+            .add_systems(OnEnter(State::Defeat), defeat)
+            .add_systems(OnExit(State::Defeat), revive)
+            .add_systems(
+                Update,
+                accept_reward.run_if(in_state(State::Reward).or_else(in_state(State::Defeat))),
+            );
     }
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Deserialize, strum_macros::EnumIter)]
 pub enum EnemyType {
     Bat,
     Ghost,
+    Skeleton,
 }
 
 impl EnemyType {
-    fn name(&self) -> &str {
+    pub fn name(&self) -> &str {
         use EnemyType::*;
 
         match self {
             Bat => "Bat",
             Ghost => "Ghost",
+            Skeleton => "Skeleton",
         }
     }
 
+    fn data_path(&self) -> String {
+        format!("enemies/{}.enemy", self.name().to_lowercase())
+    }
+
     fn exp_reward(&self) -> usize {
         use EnemyType::*;
 
         match self {
             Bat => 10,
             Ghost => 30,
+            Skeleton => 20,
         }
     }
 }
 
+/// The fight the player is about to start. Random encounters leave this empty,
+/// in which case the enemy is picked at random.
+#[derive(Resource, Default)]
+pub struct Encounter {
+    pub enemy: Option<EnemyType>,
+    /// The overworld entity that started the fight, if any.
+    pub source: Option<Entity>,
+    /// Boss fights can't be run away from.
+    pub boss: Option<Handle<boss::BossData>>,
+}
+
+/// The data files for each type of enemy.
+#[derive(Resource)]
+struct Bestiary(HashMap<EnemyType, Handle<ai::EnemyData>>);
+
 #[derive(Component)]
 struct Enemy {
-    typ: EnemyType,
+    name: String,
+    exp_reward: usize,
+    ai: Vec<ai::Rule>,
+}
+
+#[derive(Component)]
+struct Boss {
+    data: Handle<boss::BossData>,
+    phase: usize,
 }
 
 #[derive(bevy::prelude::Event)]
 pub struct Event {
+    user: Entity,
     target: Entity,
     damage_amount: isize,
+    element: damage::Element,
+    inflicts: Option<StatusEffect>,
+    animation: sequence::Sequence,
+    /// The state to go to once the animation has finished.
     next_state: State,
 }
 
-#[derive(Component, Reflect)]
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StatusEffect {
+    /// Lose a point of health at the start of each turn, though never the last.
+    Poison,
+    /// Deal a point less damage.
+    Weaken,
+}
+
+/// How many turns a status effect lasts for.
+const STATUS_TURNS: u32 = 3;
+
+/// The status effects afflicting a combatant, and how many turns each has left.
+#[derive(Component, Default)]
+pub struct Statuses(Vec<(StatusEffect, u32)>);
+
+impl Statuses {
+    fn inflict(&mut self, effect: StatusEffect) {
+        match self.0.iter_mut().find(|(existing, _)| *existing == effect) {
+            Some((_, turns)) => *turns = STATUS_TURNS,
+            None => self.0.push((effect, STATUS_TURNS)),
+        }
+    }
+
+    fn has(&self, effect: StatusEffect) -> bool {
+        self.0.iter().any(|(existing, _)| *existing == effect)
+    }
+
+    fn effects(&self) -> Vec<StatusEffect> {
+        self.0.iter().map(|(effect, _)| *effect).collect()
+    }
+}
+
+fn attack_power(stats: &Stats, statuses: &Statuses) -> isize {
+    if statuses.has(StatusEffect::Weaken) {
+        stats.attack - 1
+    } else {
+        stats.attack
+    }
+}
+
+#[derive(Component, Reflect, Clone, Deserialize)]
 pub struct Stats {
     pub health: isize,
     pub max_health: isize,
     pub attack: isize,
     pub defense: isize,
+    pub speed: isize,
 }
 
-#[derive(PartialEq, Eq, Component, Clone, Copy, strum_macros::EnumCount)]
+#[derive(PartialEq, Eq, Clone, Copy)]
 pub(crate) enum MenuOption {
-    // NOTE: the order of items here is important as we do conversions to & from
-    // `isize` in `input`. Be wary of this if changing.
     Fight,
+    Spell(damage::Element),
+    Defend,
     Run,
 }
 
-#[derive(Resource)]
-pub struct MenuSelection {
-    selected: MenuOption,
-}
+/// How much health the player recovers when defending.
+const DEFEND_REGENERATION: isize = 1;
+
+/// Marks a combatant that's defending, halving the damage they take until
+/// their next turn.
+#[derive(Component)]
+struct Guarding;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Copy, Default, States)]
 pub enum State {
     #[default]
     PlayerTurn,
+    /// The player's attack animation is playing.
     PlayerAttack,
     EnemyTurn,
+    /// The enemy's attack animation is playing.
     EnemyAttack,
+    /// The enemy was defeated.
     Reward,
+    /// The player was defeated.
+    Defeat,
     Exiting,
 }
 
-#[derive(Resource)]
-pub struct AttackAnimation {
-    timer: Timer,
-    flash_speed: f32,
-    shake: Shake,
+fn player_goes_first(mut combat_state: ResMut<NextState<State>>) {
+    combat_state.set(State::PlayerTurn);
 }
 
-pub struct Shake {
-    max_distance: f32,
-    current_distance: f32,
+fn camera(mut camera_query: Query<&mut Transform, With<Camera>>, shake: Res<sequence::Shake>) {
+    let mut camera_transform = camera_query.single_mut();
+    camera_transform.translation.x = shake.0;
+    camera_transform.translation.y = 0.;
 }
 
-impl Shake {
-    fn tick(&mut self, progress: f32) {
-        use std::f32::consts::PI;
+fn spawn_menu(mut commands: Commands, encounter: Res<Encounter>) {
+    let box_height = 3.;
+    let box_center_y = -1.0 + box_height * TILE_SIZE / 2.;
+
+    let entries = vec![
+        menu::Entry::action("Fight", MenuOption::Fight),
+        menu::Entry::submenu(
+            "Magic",
+            vec![
+                menu::Entry::action("Fire", MenuOption::Spell(damage::Element::Fire)),
+                menu::Entry::action("Ice", MenuOption::Spell(damage::Element::Ice)),
+                menu::Entry::action("Holy", MenuOption::Spell(damage::Element::Holy)),
+            ],
+        ),
+        menu::Entry::action("Defend", MenuOption::Defend),
+        // boss fights can't be run away from.
+        menu::Entry::action("Run", MenuOption::Run).enabled(encounter.boss.is_none()),
+    ];
 
-        let progress_radians = progress * (2. * PI);
-        let shake_progress = progress_radians.sin();
-
-        self.current_distance = self.max_distance * shake_progress;
-    }
+    commands
+        .spawn(SpatialBundle::from_transform(Transform::from_xyz(
+            RESOLUTION,
+            box_center_y,
+            100.,
+        )))
+        .insert(Name::new("Combat Menu"))
+        .insert(menu::Menu::new(entries, menu::Layout::RowRightAligned));
 }
 
-fn player_goes_first(mut combat_state: ResMut<NextState<State>>) {
-    combat_state.set(State::PlayerTurn);
+/// Only lets the player use the menu on their turn.
+fn activate_menu(
+    mut menu_query: Query<&mut menu::Menu<MenuOption>>,
+    combat_state: Res<bevy::prelude::State<State>>,
+) {
+    let active = combat_state.get() == &State::PlayerTurn;
+    for mut menu in menu_query.iter_mut() {
+        // only write when it changes, as any change redraws the menu.
+        if menu.active != active {
+            menu.active = active;
+        }
+    }
 }
 
-fn camera(
-    mut camera_query: Query<&mut Transform, With<Camera>>,
-    attack_animation: Res<AttackAnimation>,
-) {
-    let mut camera_transform = camera_query.single_mut();
-    camera_transform.translation.x = attack_animation.shake.current_distance;
-    camera_transform.translation.y = 0.;
+fn despawn_menu(mut commands: Commands, query: Query<Entity, With<menu::Menu<MenuOption>>>) {
+    for menu in query.iter() {
+        commands.entity(menu).despawn_recursive();
+    }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn input(
     mut commands: Commands,
-    keyboard: Res<Input<KeyCode>>,
+    mut menu_events: EventReader<menu::Event<MenuOption>>,
     mut event_writer: EventWriter<Event>,
-    player_query: Query<&Stats, With<Player>>,
-    enemy_query: Query<Entity, With<Enemy>>,
-    mut menu_state: ResMut<MenuSelection>,
+    mut player_query: Query<(Entity, &mut Stats, &Statuses), With<Player>>,
+    enemy_query: Query<(Entity, &Stats), (With<Enemy>, Without<Player>)>,
+    message_query: Query<Entity, With<Message>>,
     ascii: Res<ascii::Sheet>,
     combat_state: Res<bevy::prelude::State<State>>,
+    mut next_state: ResMut<NextState<State>>,
 ) {
-    if combat_state.get() != &State::PlayerTurn {
-        return;
+    for event in menu_events.iter() {
+        if combat_state.get() != &State::PlayerTurn {
+            continue;
+        }
+        let menu::Event::Selected { item, .. } = event else {
+            continue;
+        };
+
+        match item {
+            MenuOption::Fight => {
+                let (player, player_stats, player_statuses) = player_query.single();
+                let (target, _) = enemy_query.iter().next().unwrap();
+                event_writer.send(Event {
+                    user: player,
+                    target,
+                    damage_amount: attack_power(player_stats, player_statuses),
+                    element: damage::Element::Physical,
+                    inflicts: None,
+                    animation: sequence::Sequence::flash(),
+                    next_state: State::EnemyTurn,
+                })
+            }
+            MenuOption::Spell(element) => {
+                let (player, player_stats, player_statuses) = player_query.single();
+                let (target, _) = enemy_query.iter().next().unwrap();
+                let message = format!("You cast {element:?}!");
+                show_message(&mut commands, &ascii, &message_query, &message);
+                let (glyph, color) = match element {
+                    damage::Element::Fire => ('*', (1., 0.5, 0.1)),
+                    damage::Element::Ice => ('+', (0.6, 0.8, 1.)),
+                    damage::Element::Holy => ('o', (1., 1., 0.6)),
+                    damage::Element::Physical => ('.', (0.8, 0.8, 0.8)),
+                };
+                event_writer.send(Event {
+                    user: player,
+                    target,
+                    damage_amount: attack_power(player_stats, player_statuses),
+                    element: *element,
+                    inflicts: None,
+                    animation: sequence::Sequence::spell(glyph, color),
+                    next_state: State::EnemyTurn,
+                })
+            }
+            MenuOption::Defend => {
+                let (player, mut player_stats, _) = player_query.single_mut();
+                player_stats.health =
+                    (player_stats.health + DEFEND_REGENERATION).min(player_stats.max_health);
+                commands.entity(player).insert(Guarding);
+                show_message(&mut commands, &ascii, &message_query, "You brace yourself!");
+                next_state.set(State::EnemyTurn);
+            }
+            MenuOption::Run => {
+                let (_, player_stats, _) = player_query.single();
+                let (_, enemy_stats) = enemy_query.iter().next().unwrap();
+                if rand::random::<f32>() < escape_chance(player_stats, enemy_stats) {
+                    show_message(&mut commands, &ascii, &message_query, "Got away safely!");
+                    next_state.set(State::Exiting);
+                    fadeout::create(&mut commands, GameState::Overworld, &ascii)
+                } else {
+                    show_message(&mut commands, &ascii, &message_query, "Couldn't get away!");
+                    next_state.set(State::EnemyTurn);
+                }
+            }
+        }
     }
+}
 
-    let mut new_selection = menu_state.selected as isize;
+/// The chance of running away, which is better the faster the player is
+/// compared to the enemy.
+fn escape_chance(player: &Stats, enemy: &Stats) -> f32 {
+    (0.5 + 0.15 * (player.speed - enemy.speed) as f32).clamp(0.1, 0.95)
+}
 
-    if keyboard.just_pressed(KeyCode::A) {
-        new_selection -= 1;
-    }
+/// A line of text shown in the middle of the screen for a short while.
+#[derive(Component)]
+struct Message {
+    timer: Timer,
+}
 
-    if keyboard.just_pressed(KeyCode::D) {
-        new_selection += 1;
+fn show_message(
+    commands: &mut Commands,
+    ascii: &ascii::Sheet,
+    existing: &Query<Entity, With<Message>>,
+    message: &str,
+) {
+    for entity in existing.iter() {
+        commands.entity(entity).despawn_recursive();
     }
 
-    let menu_size = MenuOption::COUNT as isize;
+    let text = ascii::spawn_text(
+        commands,
+        ascii,
+        message,
+        Vec3::new(
+            -((message.len() / 2) as f32 * TILE_SIZE),
+            -2. * TILE_SIZE,
+            100.,
+        ),
+    );
+    commands.entity(text).insert(Text).insert(Message {
+        timer: Timer::from_seconds(1.5, TimerMode::Once),
+    });
+}
 
-    new_selection = (new_selection + menu_size) % menu_size;
+/// Marks a message saying how effective a hit was, which is shown below the
+/// other messages so that both can be read at once.
+#[derive(Component)]
+struct Feedback;
 
-    menu_state.selected = match new_selection {
-        0 => MenuOption::Fight,
-        1 => MenuOption::Run,
-        _ => unreachable!("Bad menu selection"),
-    };
+fn show_feedback(
+    commands: &mut Commands,
+    ascii: &ascii::Sheet,
+    existing: &Query<Entity, With<Feedback>>,
+    feedback: &str,
+) {
+    for entity in existing.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
 
-    if keyboard.just_pressed(KeyCode::Return) {
-        match menu_state.selected {
-            MenuOption::Fight => {
-                let player_stats = player_query.single();
-                let target = enemy_query.iter().next().unwrap();
-                event_writer.send(Event {
-                    target,
-                    damage_amount: player_stats.attack,
-                    next_state: State::PlayerAttack,
-                })
-            }
-            MenuOption::Run => fadeout::create(&mut commands, GameState::Overworld, &ascii),
+    let text = ascii::spawn_text(
+        commands,
+        ascii,
+        feedback,
+        Vec3::new(
+            -((feedback.len() / 2) as f32 * TILE_SIZE),
+            -3. * TILE_SIZE,
+            100.,
+        ),
+    );
+    commands
+        .entity(text)
+        .insert(Text)
+        .insert(Feedback)
+        .insert(Message {
+            timer: Timer::from_seconds(1.5, TimerMode::Once),
+        });
+}
+
+fn expire_messages(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Message)>,
+    time: Res<Time>,
+) {
+    for (entity, mut message) in query.iter_mut() {
+        message.timer.tick(time.delta());
+        if message.timer.finished() {
+            commands.entity(entity).despawn_recursive();
         }
     }
 }
 
+fn stop_guarding(mut commands: Commands, query: Query<Entity, With<Guarding>>) {
+    for entity in query.iter() {
+        commands.entity(entity).remove::<Guarding>();
+    }
+}
+
+#[allow(clippy::type_complexity)]
 fn damage_calculation(
     mut commands: Commands,
     ascii: Res<ascii::Sheet>,
     mut event_reader: EventReader<Event>,
-    text_query: Query<&Transform, With<Text>>,
-    mut target_query: Query<(&Children, &mut Stats)>,
+    mut target_query: Query<(
+        &mut Stats,
+        Option<&mut Statuses>,
+        Option<&damage::Affinities>,
+        Option<&Guarding>,
+        Option<&Enemy>,
+    )>,
+    feedback_query: Query<Entity, With<Feedback>>,
     mut state: ResMut<NextState<State>>,
+    mut sfx_events: EventWriter<audio::PlaySfx>,
 ) {
     for event in event_reader.iter() {
-        let (target_children, mut target_stats) = target_query
+        let (mut target_stats, target_statuses, affinities, guarding, enemy) = target_query
             .get_mut(event.target)
             .expect("Fighting target without stats!");
 
-        target_stats.health = std::cmp::max(
-            target_stats.health - (event.damage_amount - target_stats.defense),
-            0,
+        let hit = damage::calculate(
+            event.damage_amount,
+            event.element,
+            target_stats.defense,
+            affinities.unwrap_or(&damage::Affinities::default()),
+            guarding.is_some(),
         );
+        target_stats.health = std::cmp::max(target_stats.health - hit.damage, 0);
+        sfx_events.send(audio::PlaySfx::new(if hit.damage > 0 {
+            "hit"
+        } else {
+            "miss"
+        }));
+
+        if let Some(feedback) = hit.affinity.feedback() {
+            show_feedback(&mut commands, &ascii, &feedback_query, feedback);
+        }
+
+        if let (Some(effect), Some(mut statuses)) = (event.inflicts, target_statuses) {
+            statuses.inflict(effect);
+        }
+
+        if target_stats.health == 0 && enemy.is_some() {
+            // let the enemy die on screen before showing the reward.
+            state.set(State::PlayerAttack);
+            sequence::play(
+                &mut commands,
+                &event.animation.clone().then(sequence::Sequence::death()),
+                event.user,
+                event.target,
+                State::Reward,
+            );
+        } else if target_stats.health == 0 {
+            state.set(State::EnemyAttack);
+            sequence::play(
+                &mut commands,
+                &event.animation,
+                event.user,
+                event.target,
+                State::Defeat,
+            );
+        } else {
+            // whoever's turn is next, it's the other side that's attacking.
+            state.set(match event.next_state {
+                State::EnemyTurn => State::PlayerAttack,
+                _ => State::EnemyAttack,
+            });
+            sequence::play(
+                &mut commands,
+                &event.animation,
+                event.user,
+                event.target,
+                event.next_state,
+            );
+        }
+    }
+}
 
-        for child in target_children {
+fn update_health_text(
+    mut commands: Commands,
+    ascii: Res<ascii::Sheet>,
+    stats_query: Query<(Entity, &Children, &Stats), Changed<Stats>>,
+    text_query: Query<&Transform, With<Text>>,
+) {
+    for (entity, children, stats) in stats_query.iter() {
+        for child in children {
             if let Ok(transform) = text_query.get(*child) {
                 commands.entity(*child).despawn_recursive();
 
                 let new_health = ascii::spawn_text(
                     &mut commands,
                     &ascii,
-                    &format!("Health: {}", target_stats.health),
+                    &format!("Health: {}", stats.health),
                     transform.translation,
                 );
                 commands
@@ -256,70 +588,89 @@ fn damage_calculation(
                     //       overworld avatar. It must be possible to  decouple
                     //       these visibilities.
                     .insert(Visibility::Visible);
-                commands.entity(event.target).add_child(new_health);
+                commands.entity(entity).add_child(new_health);
             }
         }
+    }
+}
+
+fn tick_statuses<T: Component>(mut query: Query<(&mut Stats, &mut Statuses), With<T>>) {
+    for (mut stats, mut statuses) in query.iter_mut() {
+        if statuses.has(StatusEffect::Poison) && stats.health > 1 {
+            stats.health -= 1;
+        }
 
-        if target_stats.health == 0 {
-            state.set(State::Reward);
-        } else {
-            state.set(event.next_state);
+        for (_, turns) in statuses.0.iter_mut() {
+            *turns -= 1;
         }
+        statuses.0.retain(|(_, turns)| *turns > 0);
     }
 }
 
-fn enemy_turn(
-    mut event_writer: EventWriter<Event>,
-    enemy_query: Query<&Stats, With<Enemy>>,
-    player_query: Query<Entity, With<Player>>,
-) {
-    let player = player_query.single();
-    let enemy_stats = enemy_query.single();
-
-    event_writer.send(Event {
-        target: player,
-        damage_amount: enemy_stats.attack,
-        next_state: State::EnemyAttack,
-    })
+fn clear_statuses(mut query: Query<&mut Statuses, With<Player>>) {
+    for mut statuses in query.iter_mut() {
+        *statuses = Statuses::default();
+    }
 }
 
-fn attack_effects(
-    mut attack_animation: ResMut<AttackAnimation>,
-    time: Res<Time>,
-    mut enemy_graphics_query: Query<&mut Visibility, With<Enemy>>,
-    state: Res<bevy::prelude::State<State>>,
+#[allow(clippy::too_many_arguments)]
+fn enemy_turn(
+    mut commands: Commands,
+    ascii: Res<ascii::Sheet>,
+    mut event_writer: EventWriter<Event>,
+    mut enemy_query: Query<(Entity, &Enemy, &mut Stats, &Statuses)>,
+    player_query: Query<(Entity, &Statuses), With<Player>>,
+    message_query: Query<Entity, With<Message>>,
     mut next_state: ResMut<NextState<State>>,
 ) {
-    attack_animation.timer.tick(time.delta());
-
-    let mut enemy_visibility = enemy_graphics_query.iter_mut().next().unwrap();
-
-    match state.get() {
-        State::PlayerAttack => {
-            if attack_animation.timer.elapsed_secs() % attack_animation.flash_speed
-                > attack_animation.flash_speed / 2.
-            {
-                *enemy_visibility = Visibility::Hidden;
-            } else {
-                *enemy_visibility = Visibility::Inherited;
-            }
+    let (player, player_statuses) = player_query.single();
+    let allies = enemy_query.iter().count() - 1;
+    let (enemy_entity, enemy, mut enemy_stats, enemy_statuses) = enemy_query.single_mut();
+
+    let target_statuses = player_statuses.effects();
+    let situation = ai::Situation {
+        health: enemy_stats.health,
+        max_health: enemy_stats.max_health,
+        allies,
+        target_statuses: &target_statuses,
+    };
+    let action = ai::choose(&enemy.ai, &situation, &mut rand::thread_rng());
+
+    let name = &enemy.name;
+    match action {
+        ai::Action::Attack => event_writer.send(Event {
+            user: enemy_entity,
+            target: player,
+            damage_amount: attack_power(&enemy_stats, enemy_statuses),
+            element: damage::Element::Physical,
+            inflicts: None,
+            animation: sequence::Sequence::lunge(),
+            next_state: State::PlayerTurn,
+        }),
+        ai::Action::Skill(skill) => {
+            let message = format!("{name} used {}!", skill.name);
+            show_message(&mut commands, &ascii, &message_query, &message);
+            event_writer.send(Event {
+                user: enemy_entity,
+                target: player,
+                damage_amount: attack_power(&enemy_stats, enemy_statuses) + skill.power,
+                element: skill.element,
+                inflicts: skill.inflicts,
+                animation: skill.animation.unwrap_or_else(sequence::Sequence::lunge),
+                next_state: State::PlayerTurn,
+            })
         }
-        State::EnemyAttack => {
-            let progress = attack_animation.timer.percent();
-            attack_animation.shake.tick(progress);
+        ai::Action::Heal(amount) => {
+            enemy_stats.health = std::cmp::min(enemy_stats.health + amount, enemy_stats.max_health);
+            let message = format!("{name} healed!");
+            show_message(&mut commands, &ascii, &message_query, &message);
+            next_state.set(State::PlayerTurn);
         }
-        s => unreachable!("{}", format!("unhandled attacking state: {s:?}")),
-    }
-
-    if attack_animation.timer.just_finished() {
-        // it's possible the previous frame of the animation left the enemy
-        // invisible.
-        *enemy_visibility = Visibility::Inherited;
-
-        match state.get() {
-            State::PlayerAttack => next_state.set(State::EnemyTurn),
-            State::EnemyAttack => next_state.set(State::PlayerTurn),
-            s => unreachable!("{}", format!("unhandled attack state: {s:?}")),
+        ai::Action::Flee => {
+            let message = format!("{name} fled!");
+            show_message(&mut commands, &ascii, &message_query, &message);
+            next_state.set(State::Exiting);
+            fadeout::create(&mut commands, GameState::Overworld, &ascii);
         }
     }
 }
@@ -327,10 +678,79 @@ fn attack_effects(
 #[derive(Component)]
 struct Text;
 
-fn spawn_enemy(mut commands: Commands, ascii: Res<ascii::Sheet>, characters: Res<CharacterSheet>) {
-    let typ = select_enemy_type();
-    let stats = stats_for_enemy_type(&typ);
-    let name = typ.name().to_string();
+fn load_bestiary(mut commands: Commands, assets: Res<AssetServer>) {
+    let bestiary = EnemyType::iter()
+        .map(|typ| (typ, assets.load(typ.data_path())))
+        .collect();
+    commands.insert_resource(Bestiary(bestiary));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_enemy(
+    mut commands: Commands,
+    ascii: Res<ascii::Sheet>,
+    characters: Res<CharacterSheet>,
+    encounter: Res<Encounter>,
+    bestiary: Res<Bestiary>,
+    enemy_data: Res<Assets<ai::EnemyData>>,
+    bosses: Res<Assets<boss::BossData>>,
+    mut music_events: EventWriter<audio::MusicCommand>,
+) {
+    let boss = encounter
+        .boss
+        .as_ref()
+        .and_then(|handle| Some((handle, bosses.get(handle)?)));
+    if let (Some(handle), None) = (&encounter.boss, boss) {
+        warn!(
+            "Boss data {:?} isn't loaded, fighting a random enemy instead",
+            handle
+        );
+    }
+
+    let (typ, stats, enemy, affinities, music) = match boss {
+        Some((_, data)) => {
+            let phase = &data.phases[0];
+            let enemy = Enemy {
+                name: data.name.clone(),
+                exp_reward: data.exp_reward,
+                ai: phase.ai.clone(),
+            };
+            (
+                phase.sprite,
+                data.stats.clone(),
+                enemy,
+                phase.affinities.clone(),
+                data.music.clone(),
+            )
+        }
+        None => {
+            let typ = encounter.enemy.unwrap_or_else(select_enemy_type);
+            let (ai, affinities, music) = match bestiary
+                .0
+                .get(&typ)
+                .and_then(|handle| enemy_data.get(handle))
+            {
+                Some(data) => (data.ai.clone(), data.affinities.clone(), data.music.clone()),
+                None => {
+                    warn!(
+                        "No enemy data loaded for {}, so it'll only attack",
+                        typ.name()
+                    );
+                    (Vec::new(), damage::Affinities::default(), None)
+                }
+            };
+            let enemy = Enemy {
+                name: typ.name().to_string(),
+                exp_reward: typ.exp_reward(),
+                ai,
+            };
+            (typ, stats_for_enemy_type(&typ), enemy, affinities, music)
+        }
+    };
+
+    if let Some(music) = music {
+        music_events.send(audio::MusicCommand::Play(music));
+    }
 
     let health_text = ascii::spawn_text(
         &mut commands,
@@ -340,14 +760,69 @@ fn spawn_enemy(mut commands: Commands, ascii: Res<ascii::Sheet>, characters: Res
     );
     commands.entity(health_text).insert(Text);
 
-    let sprite = graphics::spawn_enemy(&mut commands, &typ, &characters, Vec3::new(0., 0.3, 100.));
+    let sprite = graphics::spawn_enemy(
+        &mut commands,
+        &typ,
+        &characters,
+        Vec3::new(0., 0.3, 100.),
+        0.5,
+    );
 
     commands
         .entity(sprite)
-        .insert(Enemy { typ })
+        .insert(Name::new(enemy.name.clone()))
+        .insert(enemy)
         .insert(stats)
-        .insert(Name::new(name))
+        .insert(Statuses::default())
+        .insert(affinities)
         .add_child(health_text);
+
+    if let Some((handle, _)) = boss {
+        commands.entity(sprite).insert(Boss {
+            data: handle.clone(),
+            phase: 0,
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn advance_boss_phase(
+    mut commands: Commands,
+    ascii: Res<ascii::Sheet>,
+    mut boss_query: Query<
+        (
+            &mut Boss,
+            &mut Enemy,
+            &Stats,
+            &mut damage::Affinities,
+            &mut graphics::animation::Animator,
+        ),
+        Changed<Stats>,
+    >,
+    message_query: Query<Entity, With<Message>>,
+    bosses: Res<Assets<boss::BossData>>,
+    characters: Res<CharacterSheet>,
+) {
+    for (mut boss, mut enemy, stats, mut affinities, mut animator) in boss_query.iter_mut() {
+        let Some(data) = bosses.get(&boss.data) else {
+            continue;
+        };
+
+        let phase_index = data.phase_for(stats.health, stats.max_health);
+        if phase_index == boss.phase || stats.health == 0 {
+            continue;
+        }
+
+        let phase = &data.phases[phase_index];
+        boss.phase = phase_index;
+        enemy.ai = phase.ai.clone();
+        *affinities = phase.affinities.clone();
+        animator.set_library(characters.get_enemy_animations(&phase.sprite));
+
+        if let Some(message) = &phase.message {
+            show_message(&mut commands, &ascii, &message_query, message);
+        }
+    }
 }
 
 fn stats_for_enemy_type(typ: &EnemyType) -> Stats {
@@ -357,12 +832,21 @@ fn stats_for_enemy_type(typ: &EnemyType) -> Stats {
             max_health: 3,
             attack: 2,
             defense: 1,
+            speed: 4,
         },
         EnemyType::Ghost => Stats {
             health: 5,
             max_health: 5,
             attack: 3,
             defense: 2,
+            speed: 1,
+        },
+        EnemyType::Skeleton => Stats {
+            health: 4,
+            max_health: 4,
+            attack: 2,
+            defense: 1,
+            speed: 2,
         },
     }
 }
@@ -376,6 +860,10 @@ fn select_enemy_type() -> EnemyType {
     }
 }
 
+fn clear_encounter(mut encounter: ResMut<Encounter>) {
+    *encounter = Encounter::default();
+}
+
 fn despawn_enemy(mut commands: Commands, query: Query<Entity, With<Enemy>>) {
     for entity in query.iter() {
         commands.entity(entity).despawn_recursive();
@@ -422,9 +910,11 @@ fn reward(
     ascii: Res<ascii::Sheet>,
     mut player_query: Query<(&mut Player, &mut Stats)>,
     enemy_query: Query<&Enemy>,
+    mut sfx_events: EventWriter<audio::PlaySfx>,
 ) {
+    sfx_events.send(audio::PlaySfx::new("reward"));
     let enemy = enemy_query.single();
-    let exp_reward = enemy.typ.exp_reward();
+    let exp_reward = enemy.exp_reward;
     let reward_text = format!("Earned {} exp", exp_reward);
     let text = ascii::spawn_text(
         &mut commands,
@@ -437,11 +927,12 @@ fn reward(
     match player.give_exp(exp_reward, &mut stats) {
         player::LevelUpResult::NoChange => (),
         player::LevelUpResult::LevelUp => {
+            sfx_events.send(audio::PlaySfx::new("level_up"));
             let lvl_up_text = "Level up!";
             let text = ascii::spawn_text(
                 &mut commands,
                 &ascii,
-                &lvl_up_text,
+                lvl_up_text,
                 Vec3::new(
                     -((lvl_up_text.len() / 2) as f32 * TILE_SIZE),
                     -1.5 * TILE_SIZE,
@@ -453,126 +944,62 @@ fn reward(
     }
 }
 
-fn accept_reward(
+fn boss_defeated(
     mut commands: Commands,
     ascii: Res<ascii::Sheet>,
-    keyboard: Res<Input<KeyCode>>,
-    mut next_state: ResMut<NextState<State>>,
+    encounter: Res<Encounter>,
+    bosses: Res<Assets<boss::BossData>>,
+    mut flags: ResMut<story::Flags>,
 ) {
-    if keyboard.just_pressed(KeyCode::Space) {
-        next_state.set(State::Exiting);
-        fadeout::create(&mut commands, GameState::Overworld, &ascii)
-    }
-}
-
-mod menu {
-    use bevy::prelude::*;
-
-    use crate::{ascii, RESOLUTION, TILE_SIZE};
-
-    use super::MenuOption;
-
-    pub(crate) fn spawn(
-        mut commands: Commands,
-        ascii: Res<ascii::Sheet>,
-        nineslice_indices: Res<ascii::NinesliceIndices>,
-    ) {
-        let box_height = 3.;
-        let box_center_y = -1.0 + box_height * TILE_SIZE / 2.;
-
-        let run_text = "Run";
-        let run_width = (run_text.len() + 2) as f32;
-        let run_center_x = RESOLUTION - (run_width * TILE_SIZE) / 2.;
-
-        button::spawn(
-            &mut commands,
-            &ascii,
-            &nineslice_indices,
-            Vec3::new(run_center_x, box_center_y, 100.),
-            run_text,
-            MenuOption::Run,
-            Vec2::new(run_width, box_height),
-        );
-
-        let fight_text = "Fight";
-        let fight_width = (fight_text.len() + 2) as f32;
-        let fight_center_x = RESOLUTION - (run_width * TILE_SIZE) - (fight_width * TILE_SIZE / 2.);
-
-        button::spawn(
-            &mut commands,
-            &ascii,
-            &nineslice_indices,
-            Vec3::new(fight_center_x, box_center_y, 100.),
-            fight_text,
-            MenuOption::Fight,
-            Vec2::new(fight_width, box_height),
-        );
-    }
+    let Some(data) = encounter
+        .boss
+        .as_ref()
+        .and_then(|handle| bosses.get(handle))
+    else {
+        return;
+    };
 
-    pub(crate) fn despawn(mut commands: Commands, query: Query<Entity, With<MenuOption>>) {
-        for button in query.iter() {
-            commands.entity(button).despawn_recursive();
-        }
-    }
+    flags.set(&data.flag);
 
-    pub(crate) mod button {
-        use bevy::prelude::*;
+    let text = ascii::spawn_text(
+        &mut commands,
+        &ascii,
+        &data.outro,
+        Vec3::new(
+            -((data.outro.len() / 2) as f32 * TILE_SIZE),
+            3. * TILE_SIZE,
+            0.,
+        ),
+    );
+    commands.entity(text).insert(Text);
+}
 
-        use crate::{
-            ascii,
-            combat::{MenuOption, MenuSelection},
-            TILE_SIZE,
-        };
+/// Losing sends the player back to the overworld without any of the rewards
+/// for winning, and with the enemy still there.
+fn defeat(mut commands: Commands, ascii: Res<ascii::Sheet>) {
+    let defeat_text = "You were defeated...";
+    let text = ascii::spawn_text(
+        &mut commands,
+        &ascii,
+        defeat_text,
+        Vec3::new(-((defeat_text.len() / 2) as f32 * TILE_SIZE), 0., 0.),
+    );
+    commands.entity(text).insert(Text);
+}
 
-        pub(crate) fn spawn(
-            commands: &mut Commands,
-            ascii: &ascii::Sheet,
-            indices: &ascii::NinesliceIndices,
-            translation: Vec3,
-            text: &str,
-            id: MenuOption,
-            size: Vec2,
-        ) -> Entity {
-            let nineslice = ascii::spawn_nineslice(commands, ascii, indices, size.x, size.y);
-
-            let x_offset = (-size.x / 2. + 1.5) * TILE_SIZE;
-            let text = ascii::spawn_text(commands, ascii, text, Vec3::new(x_offset, 0., 0.));
-
-            commands
-                .spawn_empty()
-                .insert(SpatialBundle::default())
-                .insert(Transform {
-                    translation,
-                    ..Default::default()
-                })
-                .insert(Name::new("Button"))
-                .insert(id)
-                .add_child(text)
-                .add_child(nineslice)
-                .id()
-        }
+fn revive(mut player_query: Query<&mut Stats, With<Player>>) {
+    let mut stats = player_query.single_mut();
+    stats.health = stats.max_health;
+}
 
-        pub(crate) fn highlight_selected(
-            menu_selection: Res<MenuSelection>,
-            button_query: Query<(&Children, &MenuOption)>,
-            nineslice_query: Query<&Children, With<ascii::Nineslice>>,
-            mut sprites_query: Query<&mut TextureAtlasSprite>,
-        ) {
-            for (button_children, button_id) in button_query.iter() {
-                for button_child in button_children.iter() {
-                    if let Ok(nineslice_children) = nineslice_query.get(*button_child) {
-                        for nineslice_child in nineslice_children.iter() {
-                            if let Ok(mut sprite) = sprites_query.get_mut(*nineslice_child) {
-                                if menu_selection.selected == *button_id {
-                                    sprite.color = Color::RED;
-                                } else {
-                                    sprite.color = Color::WHITE;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+fn accept_reward(
+    mut commands: Commands,
+    ascii: Res<ascii::Sheet>,
+    actions: Res<input::Actions>,
+    mut next_state: ResMut<NextState<State>>,
+) {
+    if actions.just_pressed(input::Action::Confirm) {
+        next_state.set(State::Exiting);
+        fadeout::create(&mut commands, GameState::Overworld, &ascii)
     }
 }
@@ -0,0 +1,245 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::{TypePath, TypeUuid},
+    utils::BoxedFuture,
+};
+use rand::Rng;
+use serde::Deserialize;
+
+use super::{damage, sequence, StatusEffect};
+
+/// The contents of a `.enemy` file, describing how an enemy behaves in combat.
+#[derive(TypeUuid, TypePath, Deserialize)]
+#[uuid = "0d6f3b3e-8c4f-4a51-b2a6-7f1e9d2c5a48"]
+pub struct EnemyData {
+    pub ai: Vec<Rule>,
+    #[serde(default)]
+    pub affinities: damage::Affinities,
+    /// The id of the music played when fighting this enemy, from the audio
+    /// manifest. Without one, the map's music carries on.
+    #[serde(default)]
+    pub music: Option<String>,
+}
+
+/// An action an enemy might take, and how likely it is to take it compared to
+/// the other rules whose conditions hold.
+#[derive(Deserialize, Clone)]
+pub struct Rule {
+    pub action: Action,
+    #[serde(default = "Rule::default_weight")]
+    pub weight: u32,
+    #[serde(default)]
+    pub when: Condition,
+}
+
+impl Rule {
+    fn default_weight() -> u32 {
+        1
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub enum Condition {
+    #[default]
+    Always,
+    /// The enemy's health is below this fraction of its maximum.
+    HealthBelow(f32),
+    /// The enemy is the last one standing.
+    Alone,
+    /// The player isn't already suffering from this status effect.
+    TargetLacks(StatusEffect),
+}
+
+#[derive(Deserialize, Clone)]
+pub enum Action {
+    Attack,
+    Skill(Skill),
+    Heal(isize),
+    Flee,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Skill {
+    pub name: String,
+    /// Damage dealt on top of the enemy's attack.
+    #[serde(default)]
+    pub power: isize,
+    #[serde(default)]
+    pub element: damage::Element,
+    /// Played when the skill is used, instead of the usual screen shake.
+    #[serde(default)]
+    pub animation: Option<sequence::Sequence>,
+    #[serde(default)]
+    pub inflicts: Option<StatusEffect>,
+}
+
+/// Everything an enemy takes into account when deciding what to do.
+pub struct Situation<'a> {
+    pub health: isize,
+    pub max_health: isize,
+    pub allies: usize,
+    pub target_statuses: &'a [StatusEffect],
+}
+
+impl Condition {
+    fn holds(&self, situation: &Situation) -> bool {
+        match self {
+            Condition::Always => true,
+            Condition::HealthBelow(fraction) => {
+                (situation.health as f32) < fraction * situation.max_health as f32
+            }
+            Condition::Alone => situation.allies == 0,
+            Condition::TargetLacks(effect) => !situation.target_statuses.contains(effect),
+        }
+    }
+}
+
+/// Picks one of the rules whose conditions hold, weighted by their weights, or
+/// a basic attack if there aren't any.
+pub fn choose(rules: &[Rule], situation: &Situation, rng: &mut impl Rng) -> Action {
+    let candidates: Vec<&Rule> = rules
+        .iter()
+        .filter(|rule| rule.weight > 0 && rule.when.holds(situation))
+        .collect();
+    let total: u32 = candidates.iter().map(|rule| rule.weight).sum();
+    if total == 0 {
+        return Action::Attack;
+    }
+
+    let mut roll = rng.gen_range(0..total);
+    for rule in candidates {
+        if roll < rule.weight {
+            return rule.action.clone();
+        }
+        roll -= rule.weight;
+    }
+
+    unreachable!("the roll is always less than the total weight")
+}
+
+#[derive(Default)]
+pub struct Loader;
+
+impl AssetLoader for Loader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let data: EnemyData = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(data));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["enemy"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn rule(action: Action, weight: u32, when: Condition) -> Rule {
+        Rule {
+            action,
+            weight,
+            when,
+        }
+    }
+
+    fn situation(health: isize, allies: usize, target_statuses: &[StatusEffect]) -> Situation<'_> {
+        Situation {
+            health,
+            max_health: 10,
+            allies,
+            target_statuses,
+        }
+    }
+
+    #[test]
+    fn rules_are_picked_in_proportion_to_their_weights() {
+        let rules = [
+            rule(Action::Attack, 3, Condition::Always),
+            rule(Action::Flee, 1, Condition::Always),
+            rule(Action::Heal(1), 0, Condition::Always),
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut attacks = 0;
+        for _ in 0..4000 {
+            match choose(&rules, &situation(10, 0, &[]), &mut rng) {
+                Action::Attack => attacks += 1,
+                Action::Flee => (),
+                _ => panic!("rules with no weight are never picked"),
+            }
+        }
+        assert!(
+            (2800..3200).contains(&attacks),
+            "{attacks} of 4000 were attacks"
+        );
+    }
+
+    #[test]
+    fn health_below_holds_under_the_fraction() {
+        let rules = [rule(Action::Heal(3), 1, Condition::HealthBelow(0.5))];
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(matches!(
+            choose(&rules, &situation(4, 0, &[]), &mut rng),
+            Action::Heal(3)
+        ));
+        assert!(matches!(
+            choose(&rules, &situation(5, 0, &[]), &mut rng),
+            Action::Attack
+        ));
+    }
+
+    #[test]
+    fn alone_holds_without_allies() {
+        let rules = [rule(Action::Flee, 1, Condition::Alone)];
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(matches!(
+            choose(&rules, &situation(10, 0, &[]), &mut rng),
+            Action::Flee
+        ));
+        assert!(matches!(
+            choose(&rules, &situation(10, 1, &[]), &mut rng),
+            Action::Attack
+        ));
+    }
+
+    #[test]
+    fn target_lacks_holds_until_the_target_has_the_effect() {
+        let rules = [rule(
+            Action::Heal(1),
+            1,
+            Condition::TargetLacks(StatusEffect::Poison),
+        )];
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(matches!(
+            choose(&rules, &situation(10, 0, &[StatusEffect::Weaken]), &mut rng),
+            Action::Heal(1)
+        ));
+        assert!(matches!(
+            choose(&rules, &situation(10, 0, &[StatusEffect::Poison]), &mut rng),
+            Action::Attack
+        ));
+    }
+
+    #[test]
+    fn falls_back_to_a_basic_attack() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(matches!(
+            choose(&[], &situation(10, 0, &[]), &mut rng),
+            Action::Attack
+        ));
+        let rules = [rule(Action::Flee, 0, Condition::Always)];
+        assert!(matches!(
+            choose(&rules, &situation(10, 0, &[]), &mut rng),
+            Action::Attack
+        ));
+    }
+}
@@ -0,0 +1,77 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::{TypePath, TypeUuid},
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use super::{ai, damage, EnemyType, Stats};
+
+/// The contents of a `.boss` file.
+#[derive(TypeUuid, TypePath, Deserialize)]
+#[uuid = "a3e4c2d1-5b6f-4e7a-8c9d-0f1b2a3c4d5e"]
+pub struct BossData {
+    pub name: String,
+    pub stats: Stats,
+    pub exp_reward: usize,
+    /// The id of the music played during the fight, from the audio manifest.
+    /// Without one, the map's music carries on.
+    #[serde(default)]
+    pub music: Option<String>,
+    /// The story flag set when the boss is defeated.
+    pub flag: String,
+    /// Said before the fight starts.
+    pub intro: String,
+    /// Said once the boss is defeated.
+    pub outro: String,
+    pub phases: Vec<Phase>,
+}
+
+/// A stage of a boss fight, which starts once the boss' health drops to the
+/// given fraction of its maximum.
+#[derive(Deserialize)]
+pub struct Phase {
+    pub below: f32,
+    pub sprite: EnemyType,
+    /// Shown when the phase starts.
+    #[serde(default)]
+    pub message: Option<String>,
+    pub ai: Vec<ai::Rule>,
+    #[serde(default)]
+    pub affinities: damage::Affinities,
+}
+
+impl BossData {
+    /// The index of the phase the boss is in at the given health.
+    pub fn phase_for(&self, health: isize, max_health: isize) -> usize {
+        let fraction = health as f32 / max_health as f32;
+        self.phases
+            .iter()
+            .rposition(|phase| fraction <= phase.below)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
+pub struct Loader;
+
+impl AssetLoader for Loader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let data: BossData = ron::de::from_bytes(bytes)?;
+            if data.phases.is_empty() {
+                return Err(bevy::asset::Error::msg("bosses need at least one phase"));
+            }
+            load_context.set_default_asset(LoadedAsset::new(data));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["boss"]
+    }
+}
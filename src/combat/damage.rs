@@ -0,0 +1,143 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+/// The kind of damage an attack deals, which some enemies are weak to or
+/// resist.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Element {
+    #[default]
+    Physical,
+    Fire,
+    Ice,
+    Holy,
+}
+
+/// How a combatant reacts to being hit with a particular element.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Affinity {
+    #[default]
+    Normal,
+    /// Takes double damage.
+    Weak,
+    /// Takes half damage.
+    Resist,
+    /// Takes no damage at all.
+    Immune,
+}
+
+impl Affinity {
+    fn apply(self, damage: isize) -> isize {
+        match self {
+            Affinity::Normal => damage,
+            Affinity::Weak => damage * 2,
+            Affinity::Resist => damage / 2,
+            Affinity::Immune => 0,
+        }
+    }
+
+    /// The text shown when a hit lands with this affinity, if any.
+    pub fn feedback(self) -> Option<&'static str> {
+        match self {
+            Affinity::Normal => None,
+            Affinity::Weak => Some("Weak!"),
+            Affinity::Resist => Some("Resist"),
+            Affinity::Immune => Some("Immune"),
+        }
+    }
+}
+
+/// A combatant's affinities, by element. Any element missing is `Normal`.
+#[derive(Component, Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Affinities(HashMap<Element, Affinity>);
+
+impl Affinities {
+    pub fn get(&self, element: Element) -> Affinity {
+        self.0.get(&element).copied().unwrap_or_default()
+    }
+}
+
+/// The outcome of an attack landing.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Hit {
+    pub damage: isize,
+    pub affinity: Affinity,
+}
+
+/// The damage dealt by an attack of the given power and element to a target
+/// with the given defense and affinities. The affinity scales the attack's
+/// power before defense is taken off, and defending halves the damage taken.
+pub fn calculate(
+    power: isize,
+    element: Element,
+    defense: isize,
+    affinities: &Affinities,
+    guarding: bool,
+) -> Hit {
+    let affinity = affinities.get(element);
+    let mut damage = (affinity.apply(power) - defense).max(0);
+    if guarding {
+        damage /= 2;
+    }
+    Hit { damage, affinity }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn affinities(element: Element, affinity: Affinity) -> Affinities {
+        let mut affinities = HashMap::new();
+        affinities.insert(element, affinity);
+        Affinities(affinities)
+    }
+
+    #[test]
+    fn normal_hits_take_off_defense() {
+        let hit = calculate(5, Element::Fire, 2, &Affinities::default(), false);
+        assert_eq!(
+            hit,
+            Hit {
+                damage: 3,
+                affinity: Affinity::Normal
+            }
+        );
+    }
+
+    #[test]
+    fn weakness_doubles_power_before_defense() {
+        let weak = affinities(Element::Holy, Affinity::Weak);
+        assert_eq!(calculate(5, Element::Holy, 2, &weak, false).damage, 8);
+        // power equal to defense would otherwise do nothing at all.
+        assert_eq!(calculate(2, Element::Holy, 2, &weak, false).damage, 2);
+        assert_eq!(calculate(2, Element::Fire, 2, &weak, false).damage, 0);
+    }
+
+    #[test]
+    fn resistance_halves_power_before_defense() {
+        let resist = affinities(Element::Physical, Affinity::Resist);
+        let hit = calculate(9, Element::Physical, 2, &resist, false);
+        assert_eq!(
+            hit,
+            Hit {
+                damage: 2,
+                affinity: Affinity::Resist
+            }
+        );
+        assert_eq!(calculate(3, Element::Physical, 2, &resist, false).damage, 0);
+    }
+
+    #[test]
+    fn immunity_prevents_all_damage() {
+        let immune = affinities(Element::Ice, Affinity::Immune);
+        assert_eq!(calculate(50, Element::Ice, 0, &immune, false).damage, 0);
+    }
+
+    #[test]
+    fn guarding_halves_damage_taken() {
+        let none = Affinities::default();
+        assert_eq!(calculate(10, Element::Physical, 2, &none, true).damage, 4);
+        let weak = affinities(Element::Fire, Affinity::Weak);
+        assert_eq!(calculate(5, Element::Fire, 2, &weak, true).damage, 4);
+    }
+}
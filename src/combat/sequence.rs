@@ -0,0 +1,406 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{ascii, audio, graphics, TILE_SIZE};
+
+use super::{Enemy, State};
+
+/// An animation played when a combatant acts, as a timeline of steps played
+/// one after the other.
+#[derive(Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Sequence(Vec<Step>);
+
+#[derive(Deserialize, Clone)]
+pub enum Step {
+    /// Does nothing for this many seconds.
+    Wait(f32),
+    /// Blinks a sprite on and off.
+    Flash {
+        who: Who,
+        duration: f32,
+        /// Seconds per blink.
+        #[serde(default = "Step::default_flash_speed")]
+        speed: f32,
+    },
+    /// Shakes the camera from side to side.
+    Shake { distance: f32, duration: f32 },
+    /// Moves a sprite by `offset` and back again.
+    Move {
+        who: Who,
+        offset: (f32, f32),
+        duration: f32,
+    },
+    /// Scatters copies of an ascii glyph from a sprite.
+    Particles {
+        who: Who,
+        glyph: char,
+        color: (f32, f32, f32),
+        count: usize,
+    },
+    /// Plays a sound effect, by its id in the audio manifest.
+    Sound(String),
+    /// Starts one of a sprite's animations, without waiting for it to finish.
+    Play { who: Who, clip: String },
+    /// Waits for a sprite's current animation clip to finish. Looping clips
+    /// never finish, so this doesn't wait for them at all; only use it after
+    /// playing a clip with `Mode::Once`.
+    AwaitClip { who: Who },
+    /// Fades a sprite out until it's invisible.
+    Fade { who: Who, duration: f32 },
+}
+
+/// Which combatant a step applies to. The player has no sprite in combat, so
+/// steps that move or flash them do nothing, and particles appear in the
+/// middle of the screen instead.
+#[derive(Deserialize, Clone, Copy)]
+pub enum Who {
+    User,
+    Target,
+}
+
+/// How far the camera is currently shaken to the side.
+#[derive(Resource, Default)]
+pub(super) struct Shake(pub f32);
+
+const PARTICLE_LIFETIME: f32 = 0.5;
+/// Tiles per second.
+const PARTICLE_SPEED: f32 = 6.;
+
+impl Step {
+    fn default_flash_speed() -> f32 {
+        0.1
+    }
+
+    fn duration(&self) -> f32 {
+        match self {
+            Step::Wait(duration)
+            | Step::Flash { duration, .. }
+            | Step::Shake { duration, .. }
+            | Step::Move { duration, .. }
+            | Step::Fade { duration, .. } => *duration,
+            Step::Particles { .. }
+            | Step::Sound(_)
+            | Step::Play { .. }
+            | Step::AwaitClip { .. } => 0.,
+        }
+    }
+}
+
+impl Sequence {
+    /// The target flinches and blinks, as when the player hits an enemy.
+    pub fn flash() -> Self {
+        Sequence(vec![
+            Step::Play {
+                who: Who::Target,
+                clip: "hurt".to_string(),
+            },
+            Step::Flash {
+                who: Who::Target,
+                duration: 0.7,
+                speed: Step::default_flash_speed(),
+            },
+        ])
+    }
+
+    /// The user lunges towards the screen, which shakes, as when an enemy hits
+    /// the player.
+    pub fn lunge() -> Self {
+        Sequence(vec![
+            Step::Play {
+                who: Who::User,
+                clip: "attack".to_string(),
+            },
+            Step::Move {
+                who: Who::User,
+                offset: (0., -0.15),
+                duration: 0.3,
+            },
+            Step::Shake {
+                distance: 0.1,
+                duration: 0.5,
+            },
+        ])
+    }
+
+    /// The target plays its death animation and fades away.
+    pub fn death() -> Self {
+        Sequence(vec![
+            Step::Play {
+                who: Who::Target,
+                clip: "death".to_string(),
+            },
+            Step::AwaitClip { who: Who::Target },
+            Step::Fade {
+                who: Who::Target,
+                duration: 0.8,
+            },
+        ])
+    }
+
+    /// Plays `next` once this sequence has finished.
+    pub fn then(mut self, next: Sequence) -> Self {
+        self.0.extend(next.0);
+        self
+    }
+
+    /// Sparks of `glyph` burst from the target, which then blinks.
+    pub fn spell(glyph: char, color: (f32, f32, f32)) -> Self {
+        Sequence(vec![
+            Step::Particles {
+                who: Who::Target,
+                glyph,
+                color,
+                count: 8,
+            },
+            Step::Wait(0.2),
+        ])
+        .then(Sequence::flash())
+    }
+}
+
+/// A sequence being played, which moves combat on to the state `then` once
+/// it's finished.
+#[derive(Component)]
+pub(super) struct Playing {
+    steps: Vec<Step>,
+    index: usize,
+    elapsed: f32,
+    started: bool,
+    user: Entity,
+    target: Entity,
+    then: State,
+    /// Where the sprite moved by the current `Move` step started.
+    origin: Option<Vec3>,
+}
+
+#[derive(Component)]
+pub(super) struct Particle {
+    velocity: Vec2,
+    lifetime: Timer,
+}
+
+pub(super) fn play(
+    commands: &mut Commands,
+    sequence: &Sequence,
+    user: Entity,
+    target: Entity,
+    then: State,
+) {
+    commands
+        .spawn_empty()
+        .insert(Name::new("Combat Animation"))
+        .insert(Playing {
+            steps: sequence.0.clone(),
+            index: 0,
+            elapsed: 0.,
+            started: false,
+            user,
+            target,
+            then,
+            origin: None,
+        });
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(super) fn advance(
+    mut commands: Commands,
+    mut playing_query: Query<(Entity, &mut Playing)>,
+    mut sprite_query: Query<
+        (
+            &mut Transform,
+            &mut Visibility,
+            &mut TextureAtlasSprite,
+            &mut graphics::animation::Animator,
+        ),
+        With<Enemy>,
+    >,
+    mut shake: ResMut<Shake>,
+    mut next_state: ResMut<NextState<State>>,
+    mut clip_events: EventReader<graphics::animation::Finished>,
+    libraries: Res<Assets<graphics::animation::Library>>,
+    ascii: Res<ascii::Sheet>,
+    mut sfx_events: EventWriter<audio::PlaySfx>,
+    time: Res<Time>,
+) {
+    let finished_clips: Vec<Entity> = clip_events.iter().map(|event| event.entity).collect();
+
+    for (entity, mut playing) in playing_query.iter_mut() {
+        playing.elapsed += time.delta_seconds();
+
+        loop {
+            let Some(step) = playing.steps.get(playing.index).cloned() else {
+                next_state.set(playing.then);
+                commands.entity(entity).despawn_recursive();
+                break;
+            };
+
+            let (user, target) = (playing.user, playing.target);
+            let sprite_of = |who: Who| match who {
+                Who::User => user,
+                Who::Target => target,
+            };
+
+            if !playing.started {
+                playing.started = true;
+                match &step {
+                    Step::Move { who, .. } => {
+                        playing.origin = sprite_query
+                            .get(sprite_of(*who))
+                            .ok()
+                            .map(|(transform, ..)| transform.translation);
+                    }
+                    Step::Particles {
+                        who,
+                        glyph,
+                        color,
+                        count,
+                    } => {
+                        let center = sprite_query
+                            .get(sprite_of(*who))
+                            .map(|(transform, ..)| transform.translation.truncate())
+                            .unwrap_or_default();
+                        spawn_particles(&mut commands, &ascii, center, *glyph, *color, *count);
+                    }
+                    Step::Sound(id) => sfx_events.send(audio::PlaySfx::new(id)),
+                    Step::Play { who, clip } => {
+                        if let Ok((.., mut animator)) = sprite_query.get_mut(sprite_of(*who)) {
+                            animator.restart(clip);
+                        }
+                    }
+                    Step::Wait(_)
+                    | Step::Flash { .. }
+                    | Step::Shake { .. }
+                    | Step::Fade { .. }
+                    | Step::AwaitClip { .. } => {}
+                }
+            }
+
+            let duration = step.duration();
+            let finished = match &step {
+                Step::AwaitClip { who } => {
+                    let sprite = sprite_of(*who);
+                    // sprites that aren't animated, or whose clip is missing,
+                    // have nothing to wait for.
+                    finished_clips.contains(&sprite)
+                        || sprite_query
+                            .get(sprite)
+                            .map_or(true, |(.., animator)| !animator.will_finish(&libraries))
+                }
+                _ => playing.elapsed >= duration,
+            };
+            let progress = if finished {
+                1.
+            } else {
+                playing.elapsed / duration
+            };
+
+            match &step {
+                Step::Flash { who, speed, .. } => {
+                    if let Ok((_, mut visibility, ..)) = sprite_query.get_mut(sprite_of(*who)) {
+                        // always end the flash visible.
+                        *visibility = if !finished && playing.elapsed % speed > speed / 2. {
+                            Visibility::Hidden
+                        } else {
+                            Visibility::Inherited
+                        };
+                    }
+                }
+                Step::Shake { distance, .. } => {
+                    shake.0 = distance * (progress * std::f32::consts::TAU).sin();
+                }
+                Step::Move { who, offset, .. } => {
+                    if let (Ok((mut transform, ..)), Some(origin)) =
+                        (sprite_query.get_mut(sprite_of(*who)), playing.origin)
+                    {
+                        let there_and_back = (progress * std::f32::consts::PI).sin();
+                        transform.translation =
+                            origin + Vec2::from(*offset).extend(0.) * there_and_back;
+                    }
+                }
+                Step::Fade { who, .. } => {
+                    if let Ok((_, _, mut sprite, ..)) = sprite_query.get_mut(sprite_of(*who)) {
+                        sprite.color.set_a(1. - progress);
+                    }
+                }
+                Step::Wait(_)
+                | Step::Particles { .. }
+                | Step::Sound(_)
+                | Step::Play { .. }
+                | Step::AwaitClip { .. } => {}
+            }
+
+            if !finished {
+                break;
+            }
+
+            // carry any leftover time into the next step, so that steps with
+            // no duration run straight away.
+            playing.elapsed = match &step {
+                Step::AwaitClip { .. } => 0.,
+                _ => playing.elapsed - duration,
+            };
+            playing.index += 1;
+            playing.started = false;
+            playing.origin = None;
+        }
+    }
+}
+
+fn spawn_particles(
+    commands: &mut Commands,
+    ascii: &ascii::Sheet,
+    center: Vec2,
+    glyph: char,
+    (r, g, b): (f32, f32, f32),
+    count: usize,
+) {
+    let mut rng = rand::thread_rng();
+    for _ in 0..count {
+        let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+        let sprite = ascii::spawn_sprite(
+            commands,
+            ascii,
+            glyph as usize,
+            Color::rgb(r, g, b),
+            center.extend(200.),
+            Vec3::splat(1.),
+        );
+        commands
+            .entity(sprite)
+            .insert(Name::new("Particle"))
+            .insert(Particle {
+                velocity: Vec2::from_angle(angle) * PARTICLE_SPEED * TILE_SIZE,
+                lifetime: Timer::from_seconds(PARTICLE_LIFETIME, TimerMode::Once),
+            });
+    }
+}
+
+pub(super) fn move_particles(
+    mut commands: Commands,
+    mut particle_query: Query<(Entity, &mut Particle, &mut Transform)>,
+    time: Res<Time>,
+) {
+    for (entity, mut particle, mut transform) in particle_query.iter_mut() {
+        particle.lifetime.tick(time.delta());
+        if particle.lifetime.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        transform.translation += (particle.velocity * time.delta_seconds()).extend(0.);
+    }
+}
+
+/// Stops any animations left playing when combat ends.
+#[allow(clippy::type_complexity)]
+pub(super) fn stop(
+    mut commands: Commands,
+    query: Query<Entity, Or<(With<Playing>, With<Particle>)>>,
+    mut shake: ResMut<Shake>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    shake.0 = 0.;
+}
@@ -0,0 +1,260 @@
+use bevy::{input::InputSystem, prelude::*};
+
+use crate::{
+    ascii, audio,
+    input::{self, Action, Actions, Binding, Bindings},
+    menu,
+    player::Player,
+    GameState, TILE_SIZE,
+};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(menu::Plugin::<ControlsOption>::default())
+            .add_systems(
+                PreUpdate,
+                // before the actions are updated, so the press being bound
+                // isn't also acted on, e.g. as confirm or mute.
+                rebind
+                    .after(InputSystem)
+                    .before(input::update)
+                    .run_if(in_state(GameState::Overworld)),
+            )
+            .add_systems(
+                Update,
+                (select, relabel.run_if(resource_changed::<Bindings>()))
+                    .chain()
+                    .run_if(in_state(GameState::Overworld)),
+            )
+            .add_systems(
+                OnExit(GameState::Overworld),
+                (menu::close::<ControlsOption>, close_prompt),
+            );
+    }
+}
+
+/// The most keys and buttons an action can have, so that its submenu fits on
+/// screen.
+const MAX_BINDINGS: usize = 4;
+
+#[derive(Clone, Copy)]
+enum ControlsOption {
+    /// Binds a new key or button to the action, in place of `old` if there is
+    /// one.
+    Rebind {
+        action: Action,
+        old: Option<Binding>,
+    },
+    Reset,
+    Back,
+}
+
+/// Added to the controls menu while it waits for the key or button to bind.
+#[derive(Component)]
+struct Rebinding {
+    action: Action,
+    old: Option<Binding>,
+    prompt: Entity,
+}
+
+/// Spawns the controls menu with its top left corner at `translation`.
+pub fn spawn(commands: &mut Commands, translation: Vec3, bindings: &Bindings) {
+    commands
+        .spawn(SpatialBundle::from_transform(Transform::from_translation(
+            translation,
+        )))
+        .insert(Name::new("Controls Menu"))
+        .insert(menu::Menu::new(entries(bindings), menu::Layout::Column).closed_by(Action::Menu));
+}
+
+fn entries(bindings: &Bindings) -> Vec<menu::Entry<ControlsOption>> {
+    let rebind_entries = |actions: &[Action]| {
+        actions
+            .iter()
+            .map(|action| {
+                let current = bindings.of(*action);
+                let first = match current.first() {
+                    Some(binding) => binding.name(),
+                    None => "-".to_string(),
+                };
+                let mut entries: Vec<_> = current
+                    .iter()
+                    .map(|binding| {
+                        let option = ControlsOption::Rebind {
+                            action: *action,
+                            old: Some(*binding),
+                        };
+                        menu::Entry::action(binding.name(), option)
+                    })
+                    .collect();
+                let add = ControlsOption::Rebind {
+                    action: *action,
+                    old: None,
+                };
+                entries.push(menu::Entry::action("Add", add).enabled(current.len() < MAX_BINDINGS));
+                menu::Entry::submenu(format!("{}: {}", action.name(), first), entries)
+            })
+            .collect()
+    };
+    vec![
+        menu::Entry::submenu(
+            "Move",
+            rebind_entries(&[Action::Up, Action::Down, Action::Left, Action::Right]),
+        ),
+        menu::Entry::submenu(
+            "Buttons",
+            rebind_entries(&[Action::Confirm, Action::Cancel, Action::Menu, Action::Mute]),
+        ),
+        menu::Entry::action("Reset", ControlsOption::Reset),
+        menu::Entry::action("Back", ControlsOption::Back),
+    ]
+}
+
+/// Shows `text` under the menu, whose submenus are never more than five
+/// entries long, with a line saying how to cancel underneath it.
+fn spawn_prompt(
+    commands: &mut Commands,
+    ascii: &ascii::Sheet,
+    menu: &Transform,
+    bindings: &Bindings,
+    text: &str,
+) -> Entity {
+    let translation = menu.translation + Vec3::new(0., -16. * TILE_SIZE, 0.);
+    let prompt = ascii::spawn_text(commands, ascii, text, translation);
+
+    let cancel: Vec<_> = bindings
+        .of(Action::Cancel)
+        .iter()
+        .map(|binding| binding.name())
+        .collect();
+    let cancel = format!("{} cancels", cancel.join("/"));
+    let cancel = ascii::spawn_text(commands, ascii, &cancel, Vec3::new(0., -TILE_SIZE, 0.));
+    commands.entity(prompt).add_child(cancel);
+    prompt
+}
+
+#[allow(clippy::too_many_arguments)]
+fn select(
+    mut commands: Commands,
+    mut events: EventReader<menu::Event<ControlsOption>>,
+    mut menu_query: Query<(&mut menu::Menu<ControlsOption>, &Transform)>,
+    mut player_query: Query<&mut Player>,
+    mut bindings: ResMut<Bindings>,
+    mut actions: ResMut<Actions>,
+    ascii: Res<ascii::Sheet>,
+    mut music_events: EventWriter<audio::MusicCommand>,
+) {
+    for event in events.iter() {
+        let menu = match event {
+            menu::Event::Selected {
+                menu,
+                item: ControlsOption::Rebind { action, old },
+            } => {
+                let Ok((mut controls, transform)) = menu_query.get_mut(*menu) else {
+                    continue;
+                };
+                controls.active = false;
+
+                let prompt = spawn_prompt(
+                    &mut commands,
+                    &ascii,
+                    transform,
+                    &bindings,
+                    "New key or button?",
+                );
+                commands.entity(*menu).insert(Rebinding {
+                    action: *action,
+                    old: *old,
+                    prompt,
+                });
+                continue;
+            }
+            menu::Event::Selected {
+                item: ControlsOption::Reset,
+                ..
+            } => {
+                *bindings = Bindings::default();
+                continue;
+            }
+            menu::Event::Selected {
+                menu,
+                item: ControlsOption::Back,
+            } => menu,
+            menu::Event::Cancelled { menu } => menu,
+        };
+
+        menu::resume_overworld(
+            &mut commands,
+            *menu,
+            &mut player_query.single_mut(),
+            &mut actions,
+            &mut music_events,
+        );
+    }
+}
+
+/// Binds the next key or button pressed to the action being rebound, unless
+/// another action already uses it. Any of cancel's bindings stops rebinding
+/// instead, since cancel can't be used to navigate while waiting. The press is consumed, so that nothing else
+/// acts on it.
+fn rebind(
+    mut commands: Commands,
+    mut menu_query: Query<(
+        Entity,
+        &mut menu::Menu<ControlsOption>,
+        &Transform,
+        &mut Rebinding,
+    )>,
+    mut keyboard: ResMut<Input<KeyCode>>,
+    mut buttons: ResMut<Input<GamepadButton>>,
+    mut bindings: ResMut<Bindings>,
+    ascii: Res<ascii::Sheet>,
+) {
+    if menu_query.is_empty() {
+        return;
+    }
+    let key = keyboard.get_just_pressed().next().copied();
+    let button = buttons.get_just_pressed().next().copied();
+    let pressed = match (key, button) {
+        (Some(key), _) => {
+            keyboard.reset(key);
+            Binding::Key(key)
+        }
+        (None, Some(button)) => {
+            buttons.reset(button);
+            Binding::Button(button.button_type)
+        }
+        (None, None) => return,
+    };
+
+    for (entity, mut menu, transform, mut rebinding) in menu_query.iter_mut() {
+        commands.entity(rebinding.prompt).despawn_recursive();
+        if !bindings.of(Action::Cancel).contains(&pressed) {
+            if let Some(other) = bindings.conflict(rebinding.action, pressed) {
+                // ask again rather than leave the other action without it.
+                let text = format!("Used by {}, try again", other.name());
+                rebinding.prompt = spawn_prompt(&mut commands, &ascii, transform, &bindings, &text);
+                continue;
+            }
+            bindings.rebind(rebinding.action, rebinding.old, pressed);
+        }
+        commands.entity(entity).remove::<Rebinding>();
+        menu.active = true;
+    }
+}
+
+fn relabel(mut menu_query: Query<&mut menu::Menu<ControlsOption>>, bindings: Res<Bindings>) {
+    for mut menu in menu_query.iter_mut() {
+        menu.set_entries(entries(&bindings));
+    }
+}
+
+/// The prompt isn't one of the menu's children, since those are replaced
+/// whenever the menu is redrawn.
+fn close_prompt(mut commands: Commands, query: Query<&Rebinding>) {
+    for rebinding in query.iter() {
+        commands.entity(rebinding.prompt).despawn_recursive();
+    }
+}
@@ -1,13 +1,25 @@
 use bevy::prelude::*;
 
-use crate::player::EncounterTracker;
+use crate::{player::EncounterTracker, GameState};
 
 pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         if cfg!(debug_assertions) {
-            app.register_type::<EncounterTracker>();
+            app.register_type::<EncounterTracker>().add_systems(
+                Update,
+                repel_encounters.run_if(in_state(GameState::Overworld)),
+            );
+        }
+    }
+}
+
+/// Stops random encounters for a while, to make testing the overworld easier.
+fn repel_encounters(keyboard: Res<Input<KeyCode>>, mut query: Query<&mut EncounterTracker>) {
+    if keyboard.just_pressed(KeyCode::R) {
+        if let Ok(mut tracker) = query.get_single_mut() {
+            tracker.repel(100);
         }
     }
 }
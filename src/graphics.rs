@@ -1,18 +1,32 @@
 use bevy::{prelude::*, utils::HashMap};
+use strum::IntoEnumIterator;
 
-use crate::combat;
+use crate::{combat, npc, TILE_SIZE};
+
+pub mod animation;
+mod sheet;
 
 pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(PreStartup, load)
-            .add_systems(PostUpdate, animate)
-            .add_systems(Update, update_player);
+        app.add_asset::<animation::Library>()
+            .init_asset_loader::<sheet::Loader>()
+            .add_event::<animation::Finished>()
+            .add_systems(PreStartup, load)
+            .add_systems(
+                PostUpdate,
+                (
+                    update_gait::<PlayerDirection>,
+                    update_gait::<NpcDirection>,
+                    animation::animate,
+                )
+                    .chain(),
+            );
     }
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Direction {
     Up,
     Down,
@@ -20,20 +34,70 @@ pub enum Direction {
     Right,
 }
 
+impl Direction {
+    /// The offset to the neighbouring tile in this direction, in tile
+    /// coordinates (where y increases downwards).
+    pub fn tile_offset(&self) -> IVec2 {
+        match self {
+            Direction::Up => IVec2::new(0, -1),
+            Direction::Down => IVec2::new(0, 1),
+            Direction::Left => IVec2::new(-1, 0),
+            Direction::Right => IVec2::new(1, 0),
+        }
+    }
+
+    /// The direction that best matches an offset in tile coordinates,
+    /// preferring left or right on diagonals.
+    pub fn towards(offset: IVec2) -> Self {
+        if offset.x.abs() >= offset.y.abs() && offset.x != 0 {
+            if offset.x > 0 {
+                Direction::Right
+            } else {
+                Direction::Left
+            }
+        } else if offset.y < 0 {
+            Direction::Up
+        } else {
+            Direction::Down
+        }
+    }
+
+    /// The name of the clip for walking in this direction.
+    pub fn walk_clip(&self) -> &'static str {
+        match self {
+            Direction::Up => "walk_up",
+            Direction::Down => "walk_down",
+            Direction::Left => "walk_left",
+            Direction::Right => "walk_right",
+        }
+    }
+
+    /// The name of the clip for standing still facing this direction.
+    pub fn idle_clip(&self) -> &'static str {
+        match self {
+            Direction::Up => "idle_up",
+            Direction::Down => "idle_down",
+            Direction::Left => "idle_left",
+            Direction::Right => "idle_right",
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct CharacterSheet {
     pub handle: Handle<TextureAtlas>,
-    pub player_frames: HashMap<Direction, [usize; 3]>,
-    pub enemy_frames: HashMap<combat::EnemyType, [usize; 3]>,
+    pub player: Handle<animation::Library>,
+    pub enemies: HashMap<combat::EnemyType, Handle<animation::Library>>,
+    pub npcs: HashMap<npc::Role, Handle<animation::Library>>,
 }
 
 impl CharacterSheet {
-    pub fn get_player_frames(&self, direction: &Direction) -> &[usize; 3] {
-        self.player_frames.get(direction).unwrap()
+    pub fn get_enemy_animations(&self, typ: &combat::EnemyType) -> Handle<animation::Library> {
+        self.enemies.get(typ).unwrap().clone()
     }
 
-    pub fn get_enemy_frames(&self, typ: &combat::EnemyType) -> &[usize; 3] {
-        self.enemy_frames.get(typ).unwrap()
+    pub fn get_npc_animations(&self, role: &npc::Role) -> Handle<animation::Library> {
+        self.npcs.get(role).unwrap().clone()
     }
 }
 
@@ -41,34 +105,53 @@ impl CharacterSheet {
 pub struct PlayerDirection(pub Direction);
 
 #[derive(Component)]
-pub struct FrameAnimation {
-    pub timer: Timer,
-    pub frames: Vec<usize>,
-    pub current_frame: usize,
+pub struct NpcDirection(pub Direction);
+
+/// A component saying which way a character is facing.
+trait Facing: Component {
+    fn facing(&self) -> Direction;
 }
 
-impl FrameAnimation {
-    fn frame(&self) -> usize {
-        self.frames[self.current_frame]
+impl Facing for PlayerDirection {
+    fn facing(&self) -> Direction {
+        self.0
     }
+}
 
-    fn tick(&mut self) {
-        self.current_frame = (self.current_frame + 1) % self.frames.len();
+impl Facing for NpcDirection {
+    fn facing(&self) -> Direction {
+        self.0
     }
 }
 
+/// Tracks how fast a character is actually moving, so that they only play
+/// their walk clip while they're walking.
+#[derive(Component, Default)]
+pub struct Gait {
+    last_position: Option<Vec3>,
+    /// How long since the character last moved, in seconds.
+    still_for: f32,
+}
+
+/// The walking speed that walk clips are timed for, in tiles per second.
+const WALK_CLIP_SPEED: f32 = 3.;
+/// How long a character must stand still before switching to their idle clip,
+/// so that a frame without movement mid-walk doesn't interrupt the walk.
+const IDLE_DELAY: f32 = 0.1;
+
 pub fn spawn_enemy(
     commands: &mut Commands,
     typ: &combat::EnemyType,
     characters: &CharacterSheet,
     translation: Vec3,
+    size: f32,
 ) -> Entity {
-    let mut sprite = TextureAtlasSprite::new(characters.get_enemy_frames(typ)[0]);
-    sprite.custom_size = Some(Vec2::splat(0.5));
-
     commands
         .spawn(SpriteSheetBundle {
-            sprite,
+            sprite: TextureAtlasSprite {
+                custom_size: Some(Vec2::splat(size)),
+                ..Default::default()
+            },
             texture_atlas: characters.handle.clone(),
             transform: Transform {
                 translation,
@@ -76,94 +159,63 @@ pub fn spawn_enemy(
             },
             ..Default::default()
         })
-        .insert(FrameAnimation {
-            timer: Timer::from_seconds(0.2, TimerMode::Repeating),
-            frames: characters.get_enemy_frames(typ).to_vec(),
-            current_frame: 0,
-        })
+        .insert(animation::Animator::new(
+            characters.get_enemy_animations(typ),
+            "idle",
+        ))
         .id()
 }
 
-fn load(
-    mut commands: Commands,
-    assets: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-) {
-    let image = assets.load("characters.png");
-    let columns = 12;
-    let atlas = TextureAtlas::from_grid(
-        image,
-        Vec2::splat(16.),
-        columns,
-        8,
-        Some(Vec2::splat(2.)),
-        None,
-    );
-    let handle = texture_atlases.add(atlas);
-
-    let player_frames = {
-        let mut f = HashMap::new();
-        f.insert(
-            Direction::Down,
-            [columns * 0 + 6, columns * 0 + 7, columns * 0 + 8],
-        );
-        f.insert(
-            Direction::Left,
-            [columns * 1 + 6, columns * 1 + 7, columns * 1 + 8],
-        );
-        f.insert(
-            Direction::Right,
-            [columns * 2 + 6, columns * 2 + 7, columns * 2 + 8],
-        );
-        f.insert(
-            Direction::Up,
-            [columns * 3 + 6, columns * 3 + 7, columns * 3 + 8],
-        );
-        f
-    };
-
-    let enemy_frames = {
-        let mut f = HashMap::new();
-        f.insert(
-            combat::EnemyType::Bat,
-            [columns * 4 + 3, columns * 4 + 4, columns * 4 + 5],
-        );
-        f.insert(
-            combat::EnemyType::Ghost,
-            [columns * 4 + 6, columns * 4 + 7, columns * 4 + 8],
-        );
-        f
-    };
+/// The manifest describing `characters.png`.
+const CHARACTER_SHEET: &str = "characters.sheet";
+
+fn load(mut commands: Commands, assets: Res<AssetServer>) {
+    let character = |name: &str| assets.load(format!("{CHARACTER_SHEET}#{name}"));
 
     commands.insert_resource(CharacterSheet {
-        handle,
-        player_frames,
-        enemy_frames,
+        handle: assets.load(format!("{CHARACTER_SHEET}#{}", sheet::ATLAS_LABEL)),
+        player: character("player"),
+        enemies: combat::EnemyType::iter()
+            .map(|typ| (typ, character(&typ.name().to_lowercase())))
+            .collect(),
+        npcs: npc::Role::iter()
+            .map(|role| (role, character(&role.name().to_lowercase())))
+            .collect(),
     });
 }
 
-fn update_player(
-    mut sprites: Query<(&PlayerDirection, &mut FrameAnimation), Changed<PlayerDirection>>,
-    characters: Res<CharacterSheet>,
+/// Plays characters' walk clips while they're moving, and their idle clips
+/// once they stop, facing whichever way `D` says.
+fn update_gait<D: Facing>(
+    mut sprites: Query<(&D, &Transform, &mut Gait, &mut animation::Animator)>,
+    time: Res<Time>,
 ) {
-    for (direction, mut animation) in sprites.iter_mut() {
-        animation.frames = characters
-            .player_frames
-            .get(&direction.0)
-            .expect("animations for each direction are inserted in `load`")
-            .to_vec();
+    let delta = time.delta_seconds();
+    if delta <= 0. {
+        return;
     }
-}
 
-fn animate(
-    mut sprites_query: Query<(&mut TextureAtlasSprite, &mut FrameAnimation)>,
-    time: Res<Time>,
-) {
-    for (mut sprite, mut animation) in sprites_query.iter_mut() {
-        animation.timer.tick(time.delta());
-        if animation.timer.just_finished() {
-            animation.tick();
-            sprite.index = animation.frame();
+    for (direction, transform, mut gait, mut animator) in sprites.iter_mut() {
+        let position = transform.translation;
+        let distance = gait
+            .last_position
+            .map_or(0., |last| last.truncate().distance(position.truncate()));
+        gait.last_position = Some(position);
+
+        if distance > 0. {
+            gait.still_for = 0.;
+            let speed = distance / TILE_SIZE / delta;
+            animator.play(direction.facing().walk_clip());
+            animator.speed = (speed / WALK_CLIP_SPEED).min(2.);
+        } else {
+            gait.still_for += delta;
+            if gait.still_for >= IDLE_DELAY {
+                animator.play(direction.facing().idle_clip());
+                animator.speed = 1.;
+            } else {
+                // keep walking, so that tile steps run together smoothly.
+                animator.play(direction.facing().walk_clip());
+            }
         }
     }
 }
@@ -0,0 +1,174 @@
+use bevy::{
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+    utils::HashMap,
+};
+use serde::Deserialize;
+
+/// The contents of a `.anim` file: a character's animation clips, by name.
+///
+/// Libraries are loaded through a `sheet::Manifest`, which places their frames
+/// on the sprite sheet, so one file can be shared by characters laid out the
+/// same way.
+#[derive(TypeUuid, TypePath, Deserialize)]
+#[uuid = "3c8a1f5e-6d2b-4f9a-a7e4-2b5d9c0e8f16"]
+#[serde(transparent)]
+pub struct Library(HashMap<String, Clip>);
+
+impl Library {
+    pub(super) fn frames_mut(&mut self) -> impl Iterator<Item = &mut Frame> {
+        self.0.values_mut().flat_map(|clip| clip.frames.iter_mut())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Clip {
+    pub frames: Vec<Frame>,
+    #[serde(default)]
+    pub mode: Mode,
+    /// The clip to play once this one finishes, for clips that play once.
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Frame {
+    /// The frame's cell in the character's region of the sheet, as
+    /// `(column, row)`.
+    pub cell: (usize, usize),
+    /// The frame's index in the texture atlas, worked out from `cell` when the
+    /// sheet is loaded.
+    #[serde(skip)]
+    pub index: usize,
+    /// How long the frame is shown for, in seconds. Must be positive.
+    #[serde(default = "Frame::default_duration")]
+    pub duration: f32,
+}
+
+impl Frame {
+    fn default_duration() -> f32 {
+        0.2
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Loop,
+    /// Plays through once, then holds the last frame.
+    Once,
+}
+
+/// Plays clips from a `Library` on an entity's `TextureAtlasSprite`.
+#[derive(Component)]
+pub struct Animator {
+    library: Handle<Library>,
+    clip: String,
+    frame: usize,
+    elapsed: f32,
+    finished: bool,
+    /// How fast clips play, as a multiple of their frames' durations.
+    pub speed: f32,
+}
+
+/// Sent when a clip played with `Mode::Once` reaches its end.
+#[derive(Event)]
+pub struct Finished {
+    pub entity: Entity,
+}
+
+impl Animator {
+    pub fn new(library: Handle<Library>, clip: &str) -> Self {
+        Animator {
+            library,
+            clip: clip.to_string(),
+            frame: 0,
+            elapsed: 0.,
+            finished: false,
+            speed: 1.,
+        }
+    }
+
+    /// Switches to the named clip, unless it's already playing.
+    pub fn play(&mut self, clip: &str) {
+        if self.clip != clip {
+            self.restart(clip);
+        }
+    }
+
+    /// Plays the named clip from the start, even if it's already playing.
+    pub fn restart(&mut self, clip: &str) {
+        self.clip = clip.to_string();
+        self.frame = 0;
+        self.elapsed = 0.;
+        self.finished = false;
+    }
+
+    /// Whether the current clip is going to send a `Finished` event, which it
+    /// won't if it loops, has already finished, or if it or its library is
+    /// missing.
+    pub fn will_finish(&self, libraries: &Assets<Library>) -> bool {
+        !self.finished
+            && libraries
+                .get(&self.library)
+                .and_then(|library| library.0.get(&self.clip))
+                .is_some_and(|clip| clip.mode == Mode::Once && !clip.frames.is_empty())
+    }
+
+    /// Plays the same clips from a different library, e.g. when a boss changes
+    /// form.
+    pub fn set_library(&mut self, library: Handle<Library>) {
+        self.library = library;
+        let clip = self.clip.clone();
+        self.restart(&clip);
+    }
+}
+
+pub(super) fn animate(
+    mut query: Query<(Entity, &mut Animator, &mut TextureAtlasSprite)>,
+    libraries: Res<Assets<Library>>,
+    mut finished_events: EventWriter<Finished>,
+    time: Res<Time>,
+) {
+    for (entity, mut animator, mut sprite) in query.iter_mut() {
+        let Some(library) = libraries.get(&animator.library) else {
+            continue;
+        };
+        let Some(clip) = library.0.get(&animator.clip) else {
+            continue;
+        };
+
+        if !animator.finished {
+            animator.elapsed += time.delta_seconds() * animator.speed;
+        }
+
+        while let Some(frame) = clip.frames.get(animator.frame) {
+            if animator.finished || animator.elapsed < frame.duration {
+                break;
+            }
+            animator.elapsed -= frame.duration;
+
+            if animator.frame + 1 < clip.frames.len() {
+                animator.frame += 1;
+            } else if clip.mode == Mode::Loop {
+                animator.frame = 0;
+            } else {
+                animator.finished = true;
+                finished_events.send(Finished { entity });
+                if let Some(next) = &clip.next {
+                    animator.restart(next);
+                }
+                break;
+            }
+        }
+
+        // the clip may have just moved on to its `next` clip.
+        if let Some(frame) = library
+            .0
+            .get(&animator.clip)
+            .and_then(|clip| clip.frames.get(animator.frame))
+        {
+            sprite.index = frame.index;
+        }
+    }
+}
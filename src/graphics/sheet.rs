@@ -0,0 +1,107 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    utils::{BoxedFuture, HashMap},
+};
+use serde::Deserialize;
+
+use super::animation::Library;
+
+/// The label of a sheet's `TextureAtlas`, e.g. `characters.sheet#atlas`.
+pub const ATLAS_LABEL: &str = "atlas";
+
+/// The contents of a `.sheet` file: how a sprite sheet image is cut into a grid
+/// of cells, and where each character is drawn on it.
+///
+/// Loading a sheet adds its `TextureAtlas`, labelled `ATLAS_LABEL`, and an
+/// animation `Library` for each character, labelled with the character's name.
+#[derive(Deserialize)]
+struct Manifest {
+    /// The image's path in the assets folder.
+    image: String,
+    /// The size of each cell, in pixels.
+    cell_size: (f32, f32),
+    columns: usize,
+    rows: usize,
+    /// The gap between neighbouring cells, in pixels.
+    #[serde(default)]
+    padding: (f32, f32),
+    /// The gap before the first cell, in pixels.
+    #[serde(default)]
+    offset: (f32, f32),
+    characters: HashMap<String, Character>,
+}
+
+#[derive(Deserialize)]
+struct Character {
+    /// The top-left cell of the character's sprites, as `(column, row)`.
+    region: (usize, usize),
+    /// A `.anim` file whose frame cells are relative to `region`.
+    animations: String,
+}
+
+impl Manifest {
+    fn index(&self, (column, row): (usize, usize)) -> Option<usize> {
+        (column < self.columns && row < self.rows).then_some(row * self.columns + column)
+    }
+}
+
+#[derive(Default)]
+pub struct Loader;
+
+impl AssetLoader for Loader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let manifest: Manifest = ron::de::from_bytes(bytes)?;
+
+            let atlas = TextureAtlas::from_grid(
+                load_context.get_handle(manifest.image.as_str()),
+                Vec2::from(manifest.cell_size),
+                manifest.columns,
+                manifest.rows,
+                Some(Vec2::from(manifest.padding)),
+                Some(Vec2::from(manifest.offset)),
+            );
+            load_context.set_labeled_asset(
+                ATLAS_LABEL,
+                LoadedAsset::new(atlas).with_dependency(manifest.image.as_str().into()),
+            );
+
+            for (name, character) in manifest.characters.iter() {
+                let bytes = load_context.read_asset_bytes(&character.animations).await?;
+                let mut library: Library = ron::de::from_bytes(&bytes)?;
+
+                let (left, top) = character.region;
+                for frame in library.frames_mut() {
+                    // `animate` would never get past a looping clip of
+                    // frames that take no time.
+                    if frame.duration.is_nan() || frame.duration <= 0. {
+                        return Err(bevy::asset::Error::msg(format!(
+                            "{name}'s frame at {:?} in {} has a duration of {}, it must be positive",
+                            frame.cell, character.animations, frame.duration
+                        )));
+                    }
+                    let (column, row) = frame.cell;
+                    frame.index = manifest.index((left + column, top + row)).ok_or_else(|| {
+                        bevy::asset::Error::msg(format!(
+                            "{name}'s frame at {:?} in {} is outside the sheet",
+                            frame.cell, character.animations
+                        ))
+                    })?;
+                }
+
+                load_context.set_labeled_asset(name, LoadedAsset::new(library));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["sheet"]
+    }
+}
@@ -0,0 +1,330 @@
+use bevy::{
+    input::InputSystem,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::util;
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(util::load_settings::<Bindings>(BINDINGS_PATH).with_defaults())
+            .init_resource::<Actions>()
+            .add_systems(PreUpdate, update.after(InputSystem))
+            .add_systems(Update, save.run_if(resource_changed::<Bindings>()));
+    }
+}
+
+/// Where the bindings are saved between runs.
+const BINDINGS_PATH: &str = "settings/input.ron";
+
+/// How far a gamepad's stick has to be pushed to count as pressing a
+/// direction.
+const STICK_DEADZONE: f32 = 0.5;
+
+/// Something the player can do, whichever key or button they use to do it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, strum_macros::EnumIter)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Cancel,
+    /// Opens the pause menu, and closes it again.
+    Menu,
+    /// Turns all sound off or back on.
+    Mute,
+}
+
+impl Action {
+    pub fn name(&self) -> &str {
+        match self {
+            Action::Up => "Up",
+            Action::Down => "Down",
+            Action::Left => "Left",
+            Action::Right => "Right",
+            Action::Confirm => "Confirm",
+            Action::Cancel => "Cancel",
+            Action::Menu => "Menu",
+            Action::Mute => "Mute",
+        }
+    }
+}
+
+/// A single key or gamepad button.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(KeyCode),
+    Button(GamepadButtonType),
+}
+
+impl Binding {
+    pub fn name(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::Button(button) => format!("Pad {:?}", button),
+        }
+    }
+}
+
+/// The keys and gamepad buttons for each action. Pressing any of them does the
+/// action. The left stick also works for the directions.
+#[derive(Resource, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Bindings {
+    pub keys: HashMap<Action, Vec<KeyCode>>,
+    pub buttons: HashMap<Action, Vec<GamepadButtonType>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let keys = Action::iter()
+            .map(|action| {
+                let keys = match action {
+                    Action::Up => vec![KeyCode::W, KeyCode::Up],
+                    Action::Down => vec![KeyCode::S, KeyCode::Down],
+                    Action::Left => vec![KeyCode::A, KeyCode::Left],
+                    Action::Right => vec![KeyCode::D, KeyCode::Right],
+                    Action::Confirm => vec![KeyCode::Return, KeyCode::Space],
+                    Action::Cancel => vec![KeyCode::Back, KeyCode::X],
+                    Action::Menu => vec![KeyCode::Escape],
+                    Action::Mute => vec![KeyCode::M],
+                };
+                (action, keys)
+            })
+            .collect();
+        let buttons = Action::iter()
+            .map(|action| {
+                let button = match action {
+                    Action::Up => GamepadButtonType::DPadUp,
+                    Action::Down => GamepadButtonType::DPadDown,
+                    Action::Left => GamepadButtonType::DPadLeft,
+                    Action::Right => GamepadButtonType::DPadRight,
+                    Action::Confirm => GamepadButtonType::South,
+                    Action::Cancel => GamepadButtonType::East,
+                    Action::Menu => GamepadButtonType::Start,
+                    Action::Mute => GamepadButtonType::Select,
+                };
+                (action, vec![button])
+            })
+            .collect();
+        Bindings { keys, buttons }
+    }
+}
+
+impl Bindings {
+    /// Fills in the default bindings for any actions that aren't bound, e.g.
+    /// ones added since the bindings were saved.
+    fn with_defaults(mut self) -> Self {
+        let defaults = Bindings::default();
+        for action in Action::iter() {
+            self.keys
+                .entry(action)
+                .or_insert_with(|| defaults.keys[&action].clone());
+            self.buttons
+                .entry(action)
+                .or_insert_with(|| defaults.buttons[&action].clone());
+        }
+        self
+    }
+
+    /// Every key and then every button bound to the action.
+    pub fn of(&self, action: Action) -> Vec<Binding> {
+        let keys = self.keys.get(&action).into_iter().flatten();
+        let buttons = self.buttons.get(&action).into_iter().flatten();
+        keys.map(|key| Binding::Key(*key))
+            .chain(buttons.map(|button| Binding::Button(*button)))
+            .collect()
+    }
+
+    /// The other action, if any, that `binding` already does.
+    pub fn conflict(&self, action: Action, binding: Binding) -> Option<Action> {
+        Action::iter().find(|other| *other != action && self.of(*other).contains(&binding))
+    }
+
+    /// Binds `binding` to the action in place of `old`, or alongside its other
+    /// bindings if there's nothing to replace.
+    pub fn rebind(&mut self, action: Action, old: Option<Binding>, binding: Binding) {
+        if self.of(action).contains(&binding) {
+            // it's already bound, so all that's left is to drop the old one.
+            if let Some(old) = old.filter(|old| *old != binding) {
+                self.unbind(action, old);
+            }
+            return;
+        }
+        match (old, binding) {
+            (Some(Binding::Key(old)), Binding::Key(key)) => {
+                let keys = self.keys.entry(action).or_default();
+                keys.iter_mut()
+                    .filter(|k| **k == old)
+                    .for_each(|k| *k = key);
+            }
+            (Some(Binding::Button(old)), Binding::Button(button)) => {
+                let buttons = self.buttons.entry(action).or_default();
+                buttons
+                    .iter_mut()
+                    .filter(|b| **b == old)
+                    .for_each(|b| *b = button);
+            }
+            (old, binding) => {
+                if let Some(old) = old {
+                    self.unbind(action, old);
+                }
+                match binding {
+                    Binding::Key(key) => self.keys.entry(action).or_default().push(key),
+                    Binding::Button(button) => self.buttons.entry(action).or_default().push(button),
+                }
+            }
+        }
+    }
+
+    fn unbind(&mut self, action: Action, binding: Binding) {
+        match binding {
+            Binding::Key(key) => {
+                if let Some(keys) = self.keys.get_mut(&action) {
+                    keys.retain(|k| *k != key);
+                }
+            }
+            Binding::Button(button) => {
+                if let Some(buttons) = self.buttons.get_mut(&action) {
+                    buttons.retain(|b| *b != button);
+                }
+            }
+        }
+    }
+}
+
+/// Which actions are being done this frame, use this instead of reading keys
+/// directly.
+#[derive(Resource, Default)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+}
+
+impl Actions {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    /// Forgets this frame's presses, so that systems which run later don't
+    /// act on the same press again.
+    pub fn clear(&mut self) {
+        self.just_pressed.clear();
+    }
+}
+
+pub fn update(
+    mut actions: ResMut<Actions>,
+    bindings: Res<Bindings>,
+    keyboard: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    gamepads: Res<Gamepads>,
+) {
+    let pressed: HashSet<Action> = Action::iter()
+        .filter(|action| {
+            let key = bindings
+                .keys
+                .get(action)
+                .into_iter()
+                .flatten()
+                .any(|key| keyboard.pressed(*key));
+            let button = gamepads.iter().any(|gamepad| {
+                bindings
+                    .buttons
+                    .get(action)
+                    .into_iter()
+                    .flatten()
+                    .any(|typ| buttons.pressed(GamepadButton::new(gamepad, *typ)))
+            });
+            let stick = gamepads
+                .iter()
+                .any(|gamepad| stick_pressed(&axes, gamepad, *action));
+            key || button || stick
+        })
+        .collect();
+
+    actions.just_pressed = pressed.difference(&actions.pressed).copied().collect();
+    actions.pressed = pressed;
+}
+
+fn stick_pressed(axes: &Axis<GamepadAxis>, gamepad: Gamepad, action: Action) -> bool {
+    let axis = |typ| axes.get(GamepadAxis::new(gamepad, typ)).unwrap_or(0.);
+    match action {
+        Action::Up => axis(GamepadAxisType::LeftStickY) > STICK_DEADZONE,
+        Action::Down => axis(GamepadAxisType::LeftStickY) < -STICK_DEADZONE,
+        Action::Left => axis(GamepadAxisType::LeftStickX) < -STICK_DEADZONE,
+        Action::Right => axis(GamepadAxisType::LeftStickX) > STICK_DEADZONE,
+        Action::Confirm | Action::Cancel | Action::Menu | Action::Mute => false,
+    }
+}
+
+fn save(bindings: Res<Bindings>) {
+    // the bindings were only just read, so there's nothing new to save.
+    if bindings.is_added() {
+        return;
+    }
+    util::save_settings(BINDINGS_PATH, &*bindings);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        let bindings = Bindings::default();
+        for action in Action::iter() {
+            for binding in bindings.of(action) {
+                assert!(bindings.conflict(action, binding).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn rebind_replaces_any_binding_in_place() {
+        let mut bindings = Bindings::default();
+        let old = Binding::Key(KeyCode::Up);
+        bindings.rebind(Action::Up, Some(old), Binding::Key(KeyCode::I));
+        assert_eq!(bindings.keys[&Action::Up], vec![KeyCode::W, KeyCode::I]);
+
+        let old = Binding::Button(GamepadButtonType::DPadUp);
+        let new = Binding::Button(GamepadButtonType::North);
+        bindings.rebind(Action::Up, Some(old), new);
+        assert_eq!(
+            bindings.buttons[&Action::Up],
+            vec![GamepadButtonType::North]
+        );
+    }
+
+    #[test]
+    fn rebind_without_old_adds() {
+        let mut bindings = Bindings::default();
+        bindings.rebind(Action::Mute, None, Binding::Key(KeyCode::N));
+        assert_eq!(bindings.keys[&Action::Mute], vec![KeyCode::M, KeyCode::N]);
+        // binding it again doesn't duplicate it.
+        bindings.rebind(Action::Mute, None, Binding::Key(KeyCode::N));
+        assert_eq!(bindings.keys[&Action::Mute], vec![KeyCode::M, KeyCode::N]);
+    }
+
+    #[test]
+    fn conflict_finds_the_other_action() {
+        let bindings = Bindings::default();
+        let space = Binding::Key(KeyCode::Space);
+        assert!(matches!(
+            bindings.conflict(Action::Cancel, space),
+            Some(Action::Confirm)
+        ));
+        assert!(bindings.conflict(Action::Confirm, space).is_none());
+    }
+}
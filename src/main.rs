@@ -1,16 +1,27 @@
 mod ascii;
 mod audio;
 mod combat;
+mod controls_menu;
 mod debug;
 mod fadeout;
 mod graphics;
+mod input;
+mod menu;
 mod npc;
+mod pause_menu;
 mod player;
+mod roaming;
 mod start_menu;
+mod story;
 mod tilemap;
 mod util;
+mod volume_menu;
 
-use bevy::{prelude::*, render::camera::ScalingMode, window::WindowResolution};
+use std::time::Duration;
+
+use bevy::{
+    asset::ChangeWatcher, prelude::*, render::camera::ScalingMode, window::WindowResolution,
+};
 
 pub const CLEAR: Color = Color::rgb(0.1, 0.1, 0.1);
 pub const RESOLUTION: f32 = 16. / 9.;
@@ -32,6 +43,15 @@ fn main() {
         .add_plugins(
             DefaultPlugins
                 .set(ImagePlugin::default_nearest())
+                .set(AssetPlugin {
+                    // hot reload assets (e.g. maps) while developing.
+                    watch_for_changes: if cfg!(debug_assertions) {
+                        ChangeWatcher::with_delay(Duration::from_millis(200))
+                    } else {
+                        None
+                    },
+                    ..Default::default()
+                })
                 .set(WindowPlugin {
                     primary_window: Some(Window {
                         resolution: WindowResolution::new(height * RESOLUTION, height),
@@ -47,13 +67,19 @@ fn main() {
         .add_plugins(ascii::Plugin)
         .add_plugins(audio::Plugin)
         .add_plugins(combat::Plugin)
+        .add_plugins(controls_menu::Plugin)
         .add_plugins(debug::Plugin)
         .add_plugins(fadeout::Plugin)
         .add_plugins(graphics::Plugin)
+        .add_plugins(input::Plugin)
         .add_plugins(npc::Plugin)
+        .add_plugins(pause_menu::Plugin)
         .add_plugins(player::Plugin)
+        .add_plugins(roaming::Plugin)
         .add_plugins(start_menu::Plugin)
+        .add_plugins(story::Plugin)
         .add_plugins(tilemap::Plugin)
+        .add_plugins(volume_menu::Plugin)
         .run();
 }
 
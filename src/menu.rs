@@ -0,0 +1,335 @@
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+
+use crate::{
+    ascii, audio,
+    input::{Action, Actions},
+    player::Player,
+    TILE_SIZE,
+};
+
+/// Adds a menu whose entries pick items of type `T`. Add one of these for each
+/// kind of menu, e.g. `menu::Plugin::<combat::MenuOption>::default()`.
+pub struct Plugin<T>(PhantomData<T>);
+
+impl<T> Default for Plugin<T> {
+    fn default() -> Self {
+        Plugin(PhantomData)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> bevy::prelude::Plugin for Plugin<T> {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Event<T>>()
+            .add_systems(Update, (navigate::<T>, redraw::<T>).chain());
+    }
+}
+
+/// A row or column of ascii buttons, navigated with the direction actions.
+///
+/// Spawn it on an entity with a `SpatialBundle`; its buttons are spawned as
+/// children of that entity.
+#[derive(Component)]
+pub struct Menu<T> {
+    entries: Vec<Entry<T>>,
+    layout: Layout,
+    /// The index of each submenu entry that's been opened, outermost first.
+    path: Vec<usize>,
+    selected: usize,
+    /// Inactive menus are still shown but ignore input.
+    pub active: bool,
+    /// An action that cancels the whole menu, even from inside a submenu.
+    closed_by: Option<Action>,
+}
+
+pub struct Entry<T> {
+    label: String,
+    enabled: bool,
+    item: Item<T>,
+}
+
+enum Item<T> {
+    Action(T),
+    Submenu(Vec<Entry<T>>),
+}
+
+/// How a menu's buttons are arranged, relative to its entity's translation.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Left to right, navigated with left and right, ending at the
+    /// translation.
+    RowRightAligned,
+    /// Top to bottom, navigated with up and down, starting at the translation.
+    Column,
+}
+
+#[derive(Event)]
+pub enum Event<T> {
+    /// An enabled action entry was picked with `Action::Confirm`.
+    Selected { menu: Entity, item: T },
+    /// `Action::Cancel` was pressed at the top level of the menu, or the
+    /// menu's `closed_by` action was pressed.
+    Cancelled { menu: Entity },
+}
+
+impl<T> Entry<T> {
+    pub fn action(label: impl Into<String>, item: T) -> Self {
+        Entry {
+            label: label.into(),
+            enabled: true,
+            item: Item::Action(item),
+        }
+    }
+
+    pub fn submenu(label: impl Into<String>, entries: Vec<Entry<T>>) -> Self {
+        Entry {
+            label: label.into(),
+            enabled: true,
+            item: Item::Submenu(entries),
+        }
+    }
+
+    /// Disabled entries are greyed out and skipped over when navigating.
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+}
+
+impl<T> Menu<T> {
+    pub fn new(entries: Vec<Entry<T>>, layout: Layout) -> Self {
+        let mut menu = Menu {
+            entries,
+            layout,
+            path: Vec::new(),
+            selected: 0,
+            active: true,
+            closed_by: None,
+        };
+        menu.selected = menu.first_enabled();
+        menu
+    }
+
+    /// Lets `action` close the menu from anywhere in it, e.g. so the action
+    /// that opened it can close it again.
+    pub fn closed_by(mut self, action: Action) -> Self {
+        self.closed_by = Some(action);
+        self
+    }
+
+    /// Swaps in new entries, e.g. to relabel them, keeping the selection and
+    /// any open submenus. The open submenus should still be there in the new
+    /// entries, though they may have gained entries.
+    pub fn set_entries(&mut self, entries: Vec<Entry<T>>) {
+        self.entries = entries;
+    }
+
+    /// The entries currently shown, i.e. those of the innermost open submenu.
+    fn current(&self) -> &[Entry<T>] {
+        let mut entries = &self.entries[..];
+        for index in self.path.iter() {
+            match &entries[*index].item {
+                Item::Submenu(submenu) => entries = submenu,
+                Item::Action(_) => unreachable!("only submenus are opened"),
+            }
+        }
+        entries
+    }
+
+    fn first_enabled(&self) -> usize {
+        self.current()
+            .iter()
+            .position(|entry| entry.enabled)
+            .unwrap_or(0)
+    }
+
+    /// Moves the selection by `offset` enabled entries, wrapping around at
+    /// either end.
+    fn step(&mut self, offset: isize) {
+        let entries = self.current();
+        let len = entries.len() as isize;
+        let mut index = self.selected as isize;
+        for _ in 0..len {
+            index = (index + offset).rem_euclid(len);
+            if entries[index as usize].enabled {
+                self.selected = index as usize;
+                return;
+            }
+        }
+    }
+}
+
+/// Despawns a menu that was shown over the overworld and hands control back to
+/// the player, resuming the music.
+pub fn resume_overworld(
+    commands: &mut Commands,
+    menu: Entity,
+    player: &mut Player,
+    actions: &mut Actions,
+    music_events: &mut EventWriter<audio::MusicCommand>,
+) {
+    commands.entity(menu).despawn_recursive();
+    // so the press that closed the menu can't also reopen the pause menu, or
+    // e.g. talk to an npc.
+    actions.clear();
+    player.active = true;
+    music_events.send(audio::MusicCommand::Resume);
+}
+
+/// Despawns every menu of type `T`, e.g. when leaving the state it's shown in.
+pub fn close<T: Send + Sync + 'static>(
+    mut commands: Commands,
+    query: Query<Entity, With<Menu<T>>>,
+) {
+    for menu in query.iter() {
+        commands.entity(menu).despawn_recursive();
+    }
+}
+
+fn navigate<T: Clone + Send + Sync + 'static>(
+    mut menu_query: Query<(Entity, &mut Menu<T>)>,
+    actions: Res<Actions>,
+    mut events: EventWriter<Event<T>>,
+    mut sfx_events: EventWriter<audio::PlaySfx>,
+) {
+    for (entity, mut menu) in menu_query.iter_mut() {
+        if !menu.active || menu.current().is_empty() {
+            continue;
+        }
+
+        if matches!(menu.closed_by, Some(action) if actions.just_pressed(action)) {
+            sfx_events.send(audio::PlaySfx::new("menu_move"));
+            events.send(Event::Cancelled { menu: entity });
+            continue;
+        }
+
+        let (back, forward) = match menu.layout {
+            Layout::RowRightAligned => (Action::Left, Action::Right),
+            Layout::Column => (Action::Up, Action::Down),
+        };
+        if actions.just_pressed(back) {
+            menu.step(-1);
+            sfx_events.send(audio::PlaySfx::new("menu_move"));
+        }
+        if actions.just_pressed(forward) {
+            menu.step(1);
+            sfx_events.send(audio::PlaySfx::new("menu_move"));
+        }
+
+        if actions.just_pressed(Action::Confirm) {
+            let entry = &menu.current()[menu.selected];
+            if !entry.enabled {
+                continue;
+            }
+            sfx_events.send(audio::PlaySfx::new("menu_select"));
+            match &entry.item {
+                Item::Action(item) => events.send(Event::Selected {
+                    menu: entity,
+                    item: item.clone(),
+                }),
+                Item::Submenu(_) => {
+                    let opened = menu.selected;
+                    menu.path.push(opened);
+                    menu.selected = menu.first_enabled();
+                }
+            }
+        } else if actions.just_pressed(Action::Cancel) {
+            sfx_events.send(audio::PlaySfx::new("menu_move"));
+            match menu.path.pop() {
+                Some(parent) => menu.selected = parent,
+                None => events.send(Event::Cancelled { menu: entity }),
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn redraw<T: Send + Sync + 'static>(
+    mut commands: Commands,
+    menu_query: Query<(Entity, &Menu<T>, Option<&Children>), Changed<Menu<T>>>,
+    ascii: Res<ascii::Sheet>,
+    indices: Res<ascii::NinesliceIndices>,
+) {
+    for (entity, menu, children) in menu_query.iter() {
+        for child in children.into_iter().flatten() {
+            commands.entity(*child).despawn_recursive();
+        }
+
+        let height = 3.;
+        let widths: Vec<f32> = menu
+            .current()
+            .iter()
+            .map(|entry| (entry.label.len() + 2) as f32)
+            .collect();
+
+        let mut offset = match menu.layout {
+            Layout::RowRightAligned => -widths.iter().sum::<f32>() * TILE_SIZE,
+            Layout::Column => 0.,
+        };
+        for (index, (entry, width)) in menu.current().iter().zip(widths).enumerate() {
+            let translation = match menu.layout {
+                Layout::RowRightAligned => Vec3::new(offset + width * TILE_SIZE / 2., 0., 0.),
+                Layout::Column => Vec3::new(width * TILE_SIZE / 2., offset, 0.),
+            };
+            let button = spawn_button(
+                &mut commands,
+                &ascii,
+                &indices,
+                translation,
+                entry,
+                index == menu.selected,
+                Vec2::new(width, height),
+            );
+            commands.entity(entity).add_child(button);
+
+            offset += match menu.layout {
+                Layout::RowRightAligned => width * TILE_SIZE,
+                Layout::Column => -height * TILE_SIZE,
+            };
+        }
+    }
+}
+
+fn spawn_button<T>(
+    commands: &mut Commands,
+    ascii: &ascii::Sheet,
+    indices: &ascii::NinesliceIndices,
+    translation: Vec3,
+    entry: &Entry<T>,
+    selected: bool,
+    size: Vec2,
+) -> Entity {
+    let nineslice = ascii::spawn_nineslice(commands, ascii, indices, size.x, size.y);
+    if selected {
+        commands.add(move |world: &mut World| recolor(world, nineslice, Color::RED));
+    }
+
+    let x_offset = (-size.x / 2. + 1.5) * TILE_SIZE;
+    let text = ascii::spawn_text(commands, ascii, &entry.label, Vec3::new(x_offset, 0., 0.));
+    if !entry.enabled {
+        commands.add(move |world: &mut World| recolor(world, text, Color::DARK_GRAY));
+    }
+
+    commands
+        .spawn_empty()
+        .insert(SpatialBundle::from_transform(Transform::from_translation(
+            translation,
+        )))
+        .insert(Name::new("Button"))
+        .add_child(text)
+        .add_child(nineslice)
+        .id()
+}
+
+/// Sets the colour of every sprite directly under `parent`.
+fn recolor(world: &mut World, parent: Entity, color: Color) {
+    let Some(children) = world.get::<Children>(parent) else {
+        return;
+    };
+    for child in children.iter().copied().collect::<Vec<_>>() {
+        if let Some(mut sprite) = world.get_mut::<TextureAtlasSprite>(child) {
+            sprite.color = color;
+        }
+    }
+}
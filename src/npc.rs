@@ -1,6 +1,13 @@
 use bevy::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
 
-use crate::{ascii, combat, player::Player, GameState, TILE_SIZE};
+use crate::{
+    ascii, combat, graphics,
+    input::{Action, Actions},
+    player::{self, Player},
+    story, tilemap, GameState, TILE_SIZE,
+};
 
 pub struct Plugin;
 
@@ -8,30 +15,306 @@ impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         // TODO: i'd prefer to have a (local?) speech state, and to react to
         //       entering & leaving that state.
-        app.add_systems(
-            Update,
-            speech
-                .before(textbox::despawn)
-                .run_if(in_state(GameState::Overworld)),
-        )
-        .add_systems(
-            Update,
-            textbox::despawn.run_if(in_state(GameState::Overworld)),
-        );
+        app.init_resource::<PendingBossBattle>()
+            .add_systems(
+                Update,
+                speech
+                    .before(textbox::despawn)
+                    .run_if(in_state(GameState::Overworld)),
+            )
+            .add_systems(
+                Update,
+                (textbox::despawn, textbox::blip).run_if(in_state(GameState::Overworld)),
+            )
+            .add_systems(Update, movement.run_if(in_state(GameState::Overworld)))
+            .add_systems(
+                Update,
+                (dress_bosses, remove_defeated_bosses).run_if(in_state(GameState::Overworld)),
+            );
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Deserialize, strum_macros::EnumIter)]
 pub enum Role {
     Healer,
 }
 
+impl Role {
+    pub fn name(&self) -> &str {
+        match self {
+            Role::Healer => "Healer",
+        }
+    }
+}
+
+/// How an npc moves around the overworld.
+#[derive(Component, Clone, Default, Deserialize)]
+pub enum Behaviour {
+    #[default]
+    Stationary,
+    /// Walks in random directions, staying within `radius` tiles of where it
+    /// was spawned.
+    Wander { radius: i32 },
+    /// Walks to each of the given tiles in turn, looping back to the first.
+    /// Walls are walked around, and waypoints that are walled off skipped.
+    Patrol { waypoints: Vec<(i32, i32)> },
+}
+
+/// A boss standing on the map, waiting for the player to talk to it.
+#[derive(Component)]
+pub struct Boss {
+    data: Handle<combat::boss::BossData>,
+    tile: IVec2,
+}
+
+/// The boss whose intro is being shown, to fight once the textbox is closed.
+#[derive(Resource, Default)]
+struct PendingBossBattle(Option<Handle<combat::boss::BossData>>);
+
+/// Tiles per second.
+const SPEED: f32 = 2.;
+/// How long a patrolling npc waits at each waypoint, in seconds.
+const WAYPOINT_PAUSE: f32 = 1.;
+/// How long an npc waits, in seconds, before trying again when something is in
+/// its way.
+const BLOCKED_PAUSE: f32 = 0.5;
+
+#[derive(Component)]
+struct Movement {
+    home: IVec2,
+    tile: IVec2,
+    step: Option<Step>,
+    next_waypoint: usize,
+    pause: Timer,
+}
+
+struct Step {
+    /// The tile being left, which stays reserved until the step finishes.
+    from_tile: IVec2,
+    from: Vec3,
+    to: Vec3,
+    progress: f32,
+}
+
+pub fn spawn(
+    commands: &mut Commands,
+    characters: &graphics::CharacterSheet,
+    role: Role,
+    behaviour: Behaviour,
+    tile: IVec2,
+) -> Entity {
+    let initial_direction = graphics::Direction::Down;
+
+    commands
+        .spawn(SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                custom_size: Some(Vec2::splat(TILE_SIZE)),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(tilemap::tile_center(tile, 800.)),
+            texture_atlas: characters.handle.clone(),
+            ..Default::default()
+        })
+        .insert(graphics::animation::Animator::new(
+            characters.get_npc_animations(&role),
+            initial_direction.idle_clip(),
+        ))
+        .insert(graphics::NpcDirection(initial_direction))
+        .insert(graphics::Gait::default())
+        .insert(Name::new("Npc"))
+        .insert(role)
+        .insert(behaviour)
+        .insert(Movement {
+            home: tile,
+            tile,
+            step: None,
+            next_waypoint: 0,
+            pause: Timer::from_seconds(0., TimerMode::Once),
+        })
+        .id()
+}
+
+/// Spawns a boss without a sprite, which is added by `dress_bosses` once its
+/// data has loaded.
+pub fn spawn_boss(
+    commands: &mut Commands,
+    data: Handle<combat::boss::BossData>,
+    tile: IVec2,
+) -> Entity {
+    commands
+        .spawn(SpatialBundle::from_transform(Transform::from_translation(
+            tilemap::tile_center(tile, 800.),
+        )))
+        .insert(Name::new("Boss"))
+        .insert(Boss { data, tile })
+        .id()
+}
+
+fn dress_bosses(
+    mut commands: Commands,
+    boss_query: Query<(Entity, &Boss), Without<TextureAtlasSprite>>,
+    bosses: Res<Assets<combat::boss::BossData>>,
+    characters: Res<graphics::CharacterSheet>,
+) {
+    for (entity, boss) in boss_query.iter() {
+        let Some(data) = bosses.get(&boss.data) else {
+            continue;
+        };
+
+        commands
+            .entity(entity)
+            .insert(TextureAtlasSprite {
+                custom_size: Some(Vec2::splat(TILE_SIZE)),
+                ..Default::default()
+            })
+            .insert(characters.handle.clone())
+            .insert(graphics::animation::Animator::new(
+                characters.get_enemy_animations(&data.phases[0].sprite),
+                "idle",
+            ));
+    }
+}
+
+fn remove_defeated_bosses(
+    mut commands: Commands,
+    boss_query: Query<(Entity, &Boss)>,
+    bosses: Res<Assets<combat::boss::BossData>>,
+    flags: Res<story::Flags>,
+    mut grid: ResMut<tilemap::Grid>,
+) {
+    for (entity, boss) in boss_query.iter() {
+        let Some(data) = bosses.get(&boss.data) else {
+            continue;
+        };
+
+        if flags.is_set(&data.flag) {
+            grid.clear_npc(entity, boss.tile);
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn movement(
+    mut npc_query: Query<
+        (
+            Entity,
+            &Behaviour,
+            &mut Movement,
+            &mut Transform,
+            &mut graphics::NpcDirection,
+        ),
+        Without<Player>,
+    >,
+    player_query: Query<(&Player, &Transform)>,
+    mut grid: ResMut<tilemap::Grid>,
+    time: Res<Time>,
+) {
+    let (player, player_transform) = player_query.single();
+
+    for (npc, behaviour, mut movement, mut transform, mut direction) in npc_query.iter_mut() {
+        if let Some(step) = &mut movement.step {
+            step.progress = (step.progress + SPEED * time.delta_seconds()).min(1.);
+            transform.translation = step.from.lerp(step.to, step.progress);
+            if step.progress < 1. {
+                continue;
+            }
+            grid.clear_npc(npc, step.from_tile);
+            movement.step = None;
+        }
+
+        // npcs hold still while the player is busy, e.g. talking to them.
+        if !player.active {
+            continue;
+        }
+
+        movement.pause.tick(time.delta());
+        if !movement.pause.finished() {
+            continue;
+        }
+
+        let Some(target) = next_tile(behaviour, &mut movement, &grid) else {
+            continue;
+        };
+        direction.0 = graphics::Direction::towards(target - movement.tile);
+
+        let blocked = grid.blocks_movement(target)
+            || tilemap::overlapped_tiles(player_transform.translation, player::PLAYER_SIZE)
+                .any(|tile| tile == target);
+        if blocked {
+            // rather than searching for a path again every frame. wandering
+            // npcs have already picked a longer pause.
+            if movement.pause.finished() {
+                movement.pause = Timer::from_seconds(BLOCKED_PAUSE, TimerMode::Once);
+            }
+            continue;
+        }
+
+        // both tiles are taken until the step finishes, so nothing walks into
+        // the npc halfway there.
+        grid.reserve_npc(npc, target);
+        movement.step = Some(Step {
+            from_tile: movement.tile,
+            from: transform.translation,
+            to: tilemap::tile_center(target, transform.translation.z),
+            progress: 0.,
+        });
+        movement.tile = target;
+    }
+}
+
+/// The tile an npc wants to walk to next, if any.
+fn next_tile(
+    behaviour: &Behaviour,
+    movement: &mut Movement,
+    grid: &tilemap::Grid,
+) -> Option<IVec2> {
+    match behaviour {
+        Behaviour::Stationary => None,
+        Behaviour::Wander { radius } => {
+            let mut rng = rand::thread_rng();
+            movement.pause = Timer::from_seconds(rng.gen_range(1.0..3.0), TimerMode::Once);
+
+            let direction = match rng.gen_range(0..4) {
+                0 => graphics::Direction::Up,
+                1 => graphics::Direction::Down,
+                2 => graphics::Direction::Left,
+                _ => graphics::Direction::Right,
+            };
+            let target = movement.tile + direction.tile_offset();
+            ((target - movement.home).abs().max_element() <= *radius).then_some(target)
+        }
+        Behaviour::Patrol { waypoints } => {
+            let (x, y) = *waypoints.get(movement.next_waypoint)?;
+            let waypoint = IVec2::new(x, y);
+            if waypoint == movement.tile {
+                movement.next_waypoint = (movement.next_waypoint + 1) % waypoints.len();
+                movement.pause = Timer::from_seconds(WAYPOINT_PAUSE, TimerMode::Once);
+                return None;
+            }
+
+            let step = grid.first_step(movement.tile, waypoint);
+            if step.is_none() {
+                // walled off, so try the next one rather than waiting forever.
+                movement.next_waypoint = (movement.next_waypoint + 1) % waypoints.len();
+                movement.pause = Timer::from_seconds(BLOCKED_PAUSE, TimerMode::Once);
+            }
+            step
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn speech(
     mut commands: Commands,
     mut player_query: Query<(&mut Player, &mut combat::Stats, &Transform)>,
     camera_query: Query<&Transform, With<Camera>>,
-    npc_query: Query<(&Role, &Transform)>,
-    mut keyboard: ResMut<Input<KeyCode>>,
+    mut npc_query: Query<(&Role, &Transform, &mut graphics::NpcDirection)>,
+    boss_query: Query<(&Boss, &Transform)>,
+    bosses: Res<Assets<combat::boss::BossData>>,
+    mut pending_battle: ResMut<PendingBossBattle>,
+    grid: Res<tilemap::Grid>,
+    mut actions: ResMut<Actions>,
     ascii: Res<ascii::Sheet>,
     indices: Res<ascii::NinesliceIndices>,
 ) {
@@ -42,8 +325,44 @@ fn speech(
         return;
     }
 
-    if keyboard.just_pressed(KeyCode::Space) {
-        for (_, npc_transform) in npc_query.iter() {
+    if actions.just_pressed(Action::Confirm) {
+        let player_tile = tilemap::tile_at(player_transform.translation);
+        let nearby_npcs = (-1..=1)
+            .flat_map(|y| (-1..=1).map(move |x| player_tile + IVec2::new(x, y)))
+            .filter_map(|tile| grid.get(tile).and_then(|cell| cell.npc));
+
+        for npc in nearby_npcs {
+            if let Ok((boss, boss_transform)) = boss_query.get(npc) {
+                let Some(data) = bosses.get(&boss.data) else {
+                    continue;
+                };
+                if boss_transform
+                    .translation
+                    .truncate()
+                    .distance(player_transform.translation.truncate())
+                    < TILE_SIZE * 1.5
+                {
+                    player.active = false;
+                    pending_battle.0 = Some(boss.data.clone());
+
+                    textbox::spawn(
+                        &mut commands,
+                        &ascii,
+                        &indices,
+                        Vec2::new(0., 1. - 1.5 * TILE_SIZE)
+                            + camera_transform.translation.truncate(),
+                        &format!("{}: {}", data.name, data.intro),
+                    );
+
+                    actions.clear();
+                    return;
+                }
+                continue;
+            }
+
+            let Ok((_, npc_transform, mut npc_direction)) = npc_query.get_mut(npc) else {
+                continue;
+            };
             if npc_transform
                 .translation
                 .truncate()
@@ -51,6 +370,9 @@ fn speech(
                 < TILE_SIZE * 1.5
             {
                 player.active = false;
+                npc_direction.0 = graphics::Direction::towards(
+                    player_tile - tilemap::tile_at(npc_transform.translation),
+                );
                 stats.health = stats.max_health;
 
                 textbox::spawn(
@@ -61,16 +383,25 @@ fn speech(
                     "It's me, Dylan!",
                 );
 
-                keyboard.clear();
+                actions.clear();
+                // an npc that's stepping between tiles is on both of them.
+                return;
             }
         }
     }
 }
 
 mod textbox {
-    use crate::{ascii, player::Player, CLEAR, TILE_SIZE};
+    use crate::{
+        ascii, audio, combat, fadeout,
+        input::{Action, Actions},
+        player::Player,
+        GameState, CLEAR, TILE_SIZE,
+    };
     use bevy::prelude::*;
 
+    use super::PendingBossBattle;
+
     #[derive(Component)]
     pub(crate) struct Text;
 
@@ -110,19 +441,44 @@ mod textbox {
             .id()
     }
 
+    /// Plays the speech sound when a textbox appears.
+    pub(crate) fn blip(query: Query<(), Added<Text>>, mut sfx_events: EventWriter<audio::PlaySfx>) {
+        if !query.is_empty() {
+            sfx_events.send(audio::PlaySfx::new("blip"));
+        }
+    }
+
     pub(crate) fn despawn(
         mut commands: Commands,
         mut player_query: Query<&mut Player>,
         speech_query: Query<Entity, With<Text>>,
-        mut keyboard: ResMut<Input<KeyCode>>,
+        mut actions: ResMut<Actions>,
+        mut pending_battle: ResMut<PendingBossBattle>,
+        mut encounter: ResMut<combat::Encounter>,
+        ascii: Res<ascii::Sheet>,
     ) {
-        if keyboard.just_pressed(KeyCode::Space) {
-            let mut player = player_query.single_mut();
-            player.active = true;
-            for entity in speech_query.iter() {
-                commands.entity(entity).despawn_recursive();
-            }
-            keyboard.clear();
+        // confirm also picks menu entries, so leave it alone unless a
+        // textbox is actually being dismissed.
+        if speech_query.is_empty() || !actions.just_pressed(Action::Confirm) {
+            return;
+        }
+        for entity in speech_query.iter() {
+            commands.entity(entity).despawn_recursive();
         }
+        actions.clear();
+
+        // the player stays inactive until the boss fight is over.
+        if let Some(boss) = pending_battle.0.take() {
+            *encounter = combat::Encounter {
+                enemy: None,
+                source: None,
+                boss: Some(boss),
+            };
+            fadeout::create(&mut commands, GameState::Combat, &ascii);
+            return;
+        }
+
+        let mut player = player_query.single_mut();
+        player.active = true;
     }
 }
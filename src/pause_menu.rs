@@ -0,0 +1,150 @@
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{
+    audio, controls_menu,
+    input::{Action, Actions, Bindings},
+    menu,
+    player::{EncounterMode, MovementMode, Player},
+    volume_menu, GameState, RESOLUTION, TILE_SIZE,
+};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(menu::Plugin::<PauseOption>::default())
+            .add_systems(
+                Update,
+                // select runs first so that the press which closes the menu
+                // has been consumed by the time open checks for it.
+                (select, open)
+                    .chain()
+                    .run_if(in_state(GameState::Overworld)),
+            )
+            .add_systems(OnExit(GameState::Overworld), menu::close::<PauseOption>);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum PauseOption {
+    Resume,
+    Movement(MovementMode),
+    Encounters(EncounterMode),
+    Volume,
+    Controls,
+    Quit,
+}
+
+fn open(
+    mut commands: Commands,
+    mut player_query: Query<&mut Player>,
+    camera_query: Query<&Transform, With<Camera>>,
+    actions: Res<Actions>,
+    movement_mode: Res<MovementMode>,
+    encounter_mode: Res<EncounterMode>,
+    mut music_events: EventWriter<audio::MusicCommand>,
+) {
+    let mut player = player_query.single_mut();
+    if !player.active || !actions.just_pressed(Action::Menu) {
+        return;
+    }
+    player.active = false;
+    music_events.send(audio::MusicCommand::Pause);
+
+    let movement_entries = [("Free", MovementMode::Free), ("Tile", MovementMode::Tile)]
+        .into_iter()
+        .map(|(label, mode)| {
+            menu::Entry::action(label, PauseOption::Movement(mode)).enabled(*movement_mode != mode)
+        })
+        .collect();
+    let encounter_entries = [
+        ("Random", EncounterMode::Random),
+        ("Visible", EncounterMode::Visible),
+    ]
+    .into_iter()
+    .map(|(label, mode)| {
+        menu::Entry::action(label, PauseOption::Encounters(mode)).enabled(*encounter_mode != mode)
+    })
+    .collect();
+    // the settings are a submenu so that the menu fits on screen.
+    let settings_entries = vec![
+        menu::Entry::submenu("Movement", movement_entries),
+        menu::Entry::submenu("Encounters", encounter_entries),
+        menu::Entry::action("Volume", PauseOption::Volume),
+        menu::Entry::action("Controls", PauseOption::Controls),
+    ];
+    let entries = vec![
+        menu::Entry::action("Resume", PauseOption::Resume),
+        menu::Entry::submenu("Settings", settings_entries),
+        menu::Entry::action("Quit", PauseOption::Quit),
+    ];
+
+    commands
+        .spawn(SpatialBundle::from_transform(Transform::from_translation(
+            top_left(&camera_query),
+        )))
+        .insert(Name::new("Pause Menu"))
+        .insert(menu::Menu::new(entries, menu::Layout::Column).closed_by(Action::Menu));
+}
+
+/// In the top left corner of the screen.
+fn top_left(camera_query: &Query<&Transform, With<Camera>>) -> Vec3 {
+    let camera = camera_query.single().translation;
+    Vec3::new(
+        camera.x - RESOLUTION + TILE_SIZE,
+        camera.y + 1. - 2.5 * TILE_SIZE,
+        950.,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn select(
+    mut commands: Commands,
+    mut events: EventReader<menu::Event<PauseOption>>,
+    mut player_query: Query<&mut Player>,
+    camera_query: Query<&Transform, With<Camera>>,
+    mut movement_mode: ResMut<MovementMode>,
+    mut encounter_mode: ResMut<EncounterMode>,
+    bindings: Res<Bindings>,
+    mixer: Res<audio::Mixer>,
+    mut actions: ResMut<Actions>,
+    mut exit: EventWriter<AppExit>,
+    mut music_events: EventWriter<audio::MusicCommand>,
+) {
+    for event in events.iter() {
+        let menu = match event {
+            menu::Event::Selected { menu, item } => {
+                match item {
+                    PauseOption::Resume => (),
+                    PauseOption::Movement(mode) => *movement_mode = *mode,
+                    PauseOption::Encounters(mode) => *encounter_mode = *mode,
+                    PauseOption::Volume => {
+                        // the game stays paused, but the music plays so that
+                        // changes to its volume can be heard.
+                        commands.entity(*menu).despawn_recursive();
+                        volume_menu::spawn(&mut commands, top_left(&camera_query), &mixer);
+                        music_events.send(audio::MusicCommand::Resume);
+                        continue;
+                    }
+                    PauseOption::Controls => {
+                        // the game stays paused until the controls menu is closed.
+                        commands.entity(*menu).despawn_recursive();
+                        controls_menu::spawn(&mut commands, top_left(&camera_query), &bindings);
+                        continue;
+                    }
+                    PauseOption::Quit => exit.send(AppExit),
+                }
+                menu
+            }
+            menu::Event::Cancelled { menu } => menu,
+        };
+
+        menu::resume_overworld(
+            &mut commands,
+            *menu,
+            &mut player_query.single_mut(),
+            &mut actions,
+            &mut music_events,
+        );
+    }
+}
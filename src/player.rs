@@ -1,8 +1,9 @@
-use bevy::{prelude::*, sprite::collide_aabb::collide};
+use bevy::prelude::*;
 
 use crate::{
-    ascii, combat, fadeout, graphics,
-    tilemap::{self, EncounterSpawner},
+    ascii, audio, combat, fadeout, graphics,
+    input::{Action, Actions},
+    tilemap,
     util::hide,
     GameState, TILE_SIZE,
 };
@@ -11,26 +12,76 @@ pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Overworld), spawn.run_if(run_once()))
+        app.init_resource::<CameraFollow>()
+            .init_resource::<MovementMode>()
+            .init_resource::<EncounterMode>()
+            .add_systems(OnEnter(GameState::Overworld), spawn.run_if(run_once()))
             .add_systems(OnEnter(GameState::Overworld), show_player)
             .add_systems(OnExit(GameState::Overworld), hide::<Player>)
+            .add_systems(OnExit(GameState::Combat), start_grace_period)
             .add_systems(
                 Update,
-                encounter_check.run_if(in_state(GameState::Overworld)),
+                encounter_check
+                    .run_if(in_state(GameState::Overworld))
+                    .run_if(resource_equals(EncounterMode::Random)),
+            )
+            .add_systems(
+                Update,
+                movement
+                    .run_if(in_state(GameState::Overworld))
+                    .run_if(resource_equals(MovementMode::Free)),
+            )
+            .add_systems(
+                Update,
+                tile_movement
+                    .run_if(in_state(GameState::Overworld))
+                    .run_if(resource_equals(MovementMode::Tile)),
+            )
+            .add_systems(
+                Update,
+                pick_up
+                    .after(movement)
+                    .after(tile_movement)
+                    .run_if(in_state(GameState::Overworld)),
+            )
+            .add_systems(
+                Update,
+                footsteps
+                    .after(movement)
+                    .after(tile_movement)
+                    .run_if(in_state(GameState::Overworld)),
             )
-            .add_systems(Update, movement.run_if(in_state(GameState::Overworld)))
             .add_systems(
                 Update,
                 camera_follow
                     .after(movement)
+                    .after(tile_movement)
                     .run_if(in_state(GameState::Overworld)),
             );
     }
 }
 
-#[derive(Component, Reflect)]
+/// How many steps the player can take after a fight before another random
+/// encounter can happen.
+const GRACE_STEPS: u32 = 4;
+
+/// How far the player walks between footstep sounds, in tiles.
+const FOOTSTEP_DISTANCE: f32 = 0.5;
+
+#[derive(Component, Reflect, Default)]
 pub struct EncounterTracker {
-    timer: Timer,
+    /// Distance walked since the last step, in tiles.
+    distance: f32,
+    last_position: Option<Vec3>,
+    grace_steps: u32,
+    repel_steps: u32,
+}
+
+impl EncounterTracker {
+    /// Prevents random encounters for the next `steps` steps.
+    pub fn repel(&mut self, steps: u32) {
+        self.repel_steps = self.repel_steps.max(steps);
+    }
 }
 
 #[derive(Component)]
@@ -41,6 +92,46 @@ pub struct Player {
     pub experience: usize,
 }
 
+/// How the player moves around the overworld.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MovementMode {
+    /// The player moves smoothly in any direction while a key is held.
+    #[default]
+    Free,
+    /// Each keypress moves the player exactly one tile. Tapping a key faces
+    /// that direction without moving.
+    Tile,
+}
+
+/// How fights are started in the overworld.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EncounterMode {
+    /// Walking on encounter tiles has a chance of starting a fight with a
+    /// random enemy.
+    #[default]
+    Random,
+    /// Enemies roam the map and start a fight when they touch the player.
+    Visible,
+}
+
+/// How long a key must be held before the player walks in a direction they
+/// weren't already facing, in `MovementMode::Tile`.
+const TURN_DURATION: f32 = 0.1;
+
+#[derive(Component, Default)]
+struct TileMovement {
+    step: Option<Step>,
+    held_for: f32,
+    turned: bool,
+}
+
+/// The player's progress walking from one tile to the next.
+struct Step {
+    from: Vec3,
+    to: Vec3,
+    progress: f32,
+}
+
 pub enum LevelUpResult {
     NoChange,
     LevelUp,
@@ -54,6 +145,7 @@ impl Player {
             stats.max_health += 2;
             stats.attack += 1;
             stats.defense += 1;
+            stats.speed += 1;
             self.experience -= 50;
 
             LevelUpResult::LevelUp
@@ -65,8 +157,8 @@ impl Player {
 
 fn movement(
     mut player_query: Query<(&Player, &mut Transform, &mut graphics::PlayerDirection)>,
-    wall_query: Query<&Transform, (With<tilemap::Collider>, Without<Player>)>,
-    keyboard: Res<Input<KeyCode>>,
+    grid: Res<tilemap::Grid>,
+    actions: Res<Actions>,
     time: Res<Time>,
 ) {
     let (player, mut transform, mut direction) = player_query.single_mut();
@@ -78,77 +170,220 @@ fn movement(
     let normalised_movement = player.speed * TILE_SIZE * time.delta_seconds();
 
     let mut y_delta = 0.;
-    if keyboard.pressed(KeyCode::W) {
+    if actions.pressed(Action::Up) {
         y_delta += normalised_movement;
     }
-    if keyboard.pressed(KeyCode::S) {
+    if actions.pressed(Action::Down) {
         y_delta -= normalised_movement;
     }
 
     let mut x_delta = 0.;
-    if keyboard.pressed(KeyCode::A) {
+    if actions.pressed(Action::Left) {
         x_delta -= normalised_movement;
     }
-    if keyboard.pressed(KeyCode::D) {
+    if actions.pressed(Action::Right) {
         x_delta += normalised_movement;
     }
 
+    // the directions the player actually moved in, left or right first.
+    let mut moved = Vec::new();
+
+    let target = transform.translation + Vec3::new(x_delta, 0., 0.);
+    if x_delta != 0. && !would_collide(&grid, target) {
+        moved.push(if x_delta > 0. {
+            graphics::Direction::Right
+        } else {
+            graphics::Direction::Left
+        });
+        transform.translation = target;
+    }
+
     let target = transform.translation + Vec3::new(0., y_delta, 0.);
-    if !wall_query
-        .iter()
-        .any(|&wall| would_collide(target, wall.translation))
-    {
-        if y_delta > 0. {
-            direction.0 = graphics::Direction::Up;
-        } else if y_delta < 0. {
-            direction.0 = graphics::Direction::Down;
-        }
+    if y_delta != 0. && !would_collide(&grid, target) {
+        moved.push(if y_delta > 0. {
+            graphics::Direction::Up
+        } else {
+            graphics::Direction::Down
+        });
         transform.translation = target;
     }
 
-    let target = transform.translation + Vec3::new(x_delta, 0., 0.);
-    if !wall_query
-        .iter()
-        .any(|&wall| would_collide(target, wall.translation))
-    {
-        if x_delta > 0. {
-            direction.0 = graphics::Direction::Right;
-        } else if x_delta < 0. {
-            direction.0 = graphics::Direction::Left;
+    // keep facing the same way when a diagonal includes it, so that adding a
+    // second key doesn't flip the sprite around.
+    if !moved.contains(&direction.0) {
+        if let Some(facing) = moved.first() {
+            direction.0 = *facing;
         }
-        transform.translation = target;
     }
 }
 
-fn would_collide(target_player_pos: Vec3, wall: Vec3) -> bool {
-    collide(
-        target_player_pos,
-        Vec2::splat(TILE_SIZE * 0.9),
-        wall,
-        Vec2::splat(TILE_SIZE),
-    )
-    .is_some()
+fn tile_movement(
+    mut player_query: Query<(
+        &Player,
+        &mut TileMovement,
+        &mut Transform,
+        &mut graphics::PlayerDirection,
+    )>,
+    grid: Res<tilemap::Grid>,
+    actions: Res<Actions>,
+    time: Res<Time>,
+) {
+    let (player, mut movement, mut transform, mut direction) = player_query.single_mut();
+
+    if let Some(step) = &mut movement.step {
+        step.progress = (step.progress + player.speed * time.delta_seconds()).min(1.);
+        transform.translation = step.from.lerp(step.to, step.progress);
+        if step.progress < 1. {
+            return;
+        }
+        movement.step = None;
+    }
+
+    if !player.active {
+        return;
+    }
+
+    let input = if actions.pressed(Action::Up) {
+        Some(graphics::Direction::Up)
+    } else if actions.pressed(Action::Down) {
+        Some(graphics::Direction::Down)
+    } else if actions.pressed(Action::Left) {
+        Some(graphics::Direction::Left)
+    } else if actions.pressed(Action::Right) {
+        Some(graphics::Direction::Right)
+    } else {
+        None
+    };
+
+    let Some(input) = input else {
+        movement.held_for = 0.;
+        movement.turned = false;
+        return;
+    };
+
+    if direction.0 != input {
+        // only a fresh press turns on the spot. changing direction mid-walk
+        // carries straight on in the new direction.
+        movement.turned = movement.held_for == 0.;
+        direction.0 = input;
+    }
+
+    movement.held_for += time.delta_seconds();
+    if movement.turned && movement.held_for < TURN_DURATION {
+        return;
+    }
+    movement.turned = false;
+
+    let current_tile = tilemap::tile_at(transform.translation);
+    let target_tile = current_tile + direction.0.tile_offset();
+    if grid.blocks_movement(target_tile) {
+        return;
+    }
+
+    movement.step = Some(Step {
+        from: transform.translation,
+        to: tilemap::tile_center(target_tile, transform.translation.z),
+        progress: 0.,
+    });
+}
+
+pub(crate) const PLAYER_SIZE: f32 = TILE_SIZE * 0.9;
+
+fn would_collide(grid: &tilemap::Grid, target_player_pos: Vec3) -> bool {
+    grid.overlapping(target_player_pos, PLAYER_SIZE)
+        .any(|cell| cell.blocks_movement())
 }
 
 fn encounter_check(
     mut commands: Commands,
     mut player_query: Query<(&mut Player, &mut EncounterTracker, &Transform)>,
-    encounter_query: Query<&Transform, (With<EncounterSpawner>, Without<Player>)>,
+    grid: Res<tilemap::Grid>,
     ascii: Res<ascii::Sheet>,
-    time: Res<Time>,
 ) {
-    let (mut player, mut encounter_tracker, player_transform) = player_query.single_mut();
-    let player_pos = player_transform.translation;
-    if encounter_query
-        .iter()
-        .any(|&encounter_tile| would_collide(player_pos, encounter_tile.translation))
-    {
-        encounter_tracker.timer.tick(time.delta());
-        if encounter_tracker.timer.just_finished() {
+    let (mut player, mut tracker, player_transform) = player_query.single_mut();
+    let position = player_transform.translation;
+
+    if let Some(last_position) = tracker.last_position {
+        tracker.distance += last_position.distance(position) / TILE_SIZE;
+    }
+    tracker.last_position = Some(position);
+
+    if !player.active {
+        return;
+    }
+
+    while tracker.distance >= 1. {
+        tracker.distance -= 1.;
+
+        if tracker.grace_steps > 0 {
+            tracker.grace_steps -= 1;
+            continue;
+        }
+        if tracker.repel_steps > 0 {
+            tracker.repel_steps -= 1;
+            continue;
+        }
+
+        let rate = grid
+            .get(tilemap::tile_at(position))
+            .map_or(0., |cell| cell.encounter_rate);
+        if rand::random::<f32>() < rate {
+            tracker.distance = 0.;
             player.active = false;
-            fadeout::create(&mut commands, GameState::Combat, &ascii)
+            fadeout::create(&mut commands, GameState::Combat, &ascii);
+            return;
+        }
+    }
+}
+
+/// Plays a footstep sound every `FOOTSTEP_DISTANCE` walked.
+fn footsteps(
+    player_query: Query<&Transform, With<Player>>,
+    mut last_position: Local<Option<Vec3>>,
+    mut distance: Local<f32>,
+    mut sfx_events: EventWriter<audio::PlaySfx>,
+) {
+    let position = player_query.single().translation;
+    if let Some(last_position) = *last_position {
+        let walked = last_position.distance(position) / TILE_SIZE;
+        // anything further than a tile in one frame isn't walking.
+        if walked < 1. {
+            *distance += walked;
         }
     }
+    *last_position = Some(position);
+
+    if *distance >= FOOTSTEP_DISTANCE {
+        *distance %= FOOTSTEP_DISTANCE;
+        sfx_events.send(audio::PlaySfx::new("footstep"));
+    }
+}
+
+fn pick_up(
+    mut commands: Commands,
+    mut player_query: Query<(&Transform, &mut EncounterTracker), With<Player>>,
+    pickup_query: Query<(Entity, &Transform, &tilemap::Pickup)>,
+    mut sfx_events: EventWriter<audio::PlaySfx>,
+) {
+    let (player_transform, mut tracker) = player_query.single_mut();
+    let player_tile = tilemap::tile_at(player_transform.translation);
+
+    for (entity, transform, pickup) in pickup_query.iter() {
+        if tilemap::tile_at(transform.translation) != player_tile {
+            continue;
+        }
+        match pickup {
+            tilemap::Pickup::Repel { steps } => tracker.repel(*steps),
+        }
+        commands.entity(entity).despawn_recursive();
+        sfx_events.send(audio::PlaySfx::new("reward"));
+    }
+}
+
+fn start_grace_period(mut query: Query<&mut EncounterTracker>) {
+    if let Ok(mut tracker) = query.get_single_mut() {
+        tracker.grace_steps = GRACE_STEPS;
+    }
 }
 
 fn show_player(mut query: Query<(&mut Player, &mut Visibility)>) {
@@ -160,12 +395,10 @@ fn show_player(mut query: Query<(&mut Player, &mut Visibility)>) {
 
 fn spawn(mut commands: Commands, characters: Res<graphics::CharacterSheet>) {
     let initial_direction = graphics::Direction::Down;
-    let initial_frames = characters.get_player_frames(&initial_direction);
     commands
         // TODO: DirectionalAnimationBundle to configure all movement-related stuff?
         .spawn(SpriteSheetBundle {
             sprite: TextureAtlasSprite {
-                index: initial_frames[0],
                 custom_size: Some(Vec2::splat(TILE_SIZE)),
                 ..Default::default()
             },
@@ -173,12 +406,13 @@ fn spawn(mut commands: Commands, characters: Res<graphics::CharacterSheet>) {
             texture_atlas: characters.handle.clone(),
             ..Default::default()
         })
-        .insert(graphics::FrameAnimation {
-            timer: Timer::from_seconds(0.2, TimerMode::Repeating),
-            frames: initial_frames.to_vec(),
-            current_frame: 0,
-        })
+        .insert(graphics::animation::Animator::new(
+            characters.player.clone(),
+            initial_direction.idle_clip(),
+        ))
         .insert(graphics::PlayerDirection(initial_direction))
+        .insert(graphics::Gait::default())
+        .insert(TileMovement::default())
         .insert(Name::new("Player"))
         .insert(Player {
             speed: 3.,
@@ -190,19 +424,71 @@ fn spawn(mut commands: Commands, characters: Res<graphics::CharacterSheet>) {
             max_health: 10,
             attack: 2,
             defense: 1,
+            speed: 2,
         })
-        .insert(EncounterTracker {
-            timer: Timer::from_seconds(1., TimerMode::Repeating),
-        });
+        .insert(combat::Statuses::default())
+        .insert(EncounterTracker::default());
+}
+
+/// How the overworld camera tracks the player.
+#[derive(Resource)]
+pub struct CameraFollow {
+    /// Half the size of the area around the centre of the screen that the
+    /// player can move within without the camera moving.
+    pub deadzone: Vec2,
+    /// How quickly the camera catches up with the player, or `None` to have it
+    /// move in lockstep.
+    pub smoothing: Option<f32>,
+}
+
+impl Default for CameraFollow {
+    fn default() -> Self {
+        CameraFollow {
+            deadzone: Vec2::ZERO,
+            smoothing: None,
+        }
+    }
 }
 
-fn camera_follow(
+#[allow(clippy::type_complexity)]
+pub(crate) fn camera_follow(
     player_query: Query<&Transform, With<Player>>,
-    mut camera_query: Query<&mut Transform, (Without<Player>, With<Camera>)>,
+    mut camera_query: Query<
+        (&mut Transform, &OrthographicProjection),
+        (Without<Player>, With<Camera>),
+    >,
+    follow: Res<CameraFollow>,
+    grid: Res<tilemap::Grid>,
+    time: Res<Time>,
 ) {
     let player_transform = player_query.single();
-    let mut camera_transform = camera_query.single_mut();
+    let (mut camera_transform, projection) = camera_query.single_mut();
+
+    let player = player_transform.translation.truncate();
+    let camera = camera_transform.translation.truncate();
+    let offset = player - camera;
+    let mut target = camera + offset - offset.clamp(-follow.deadzone, follow.deadzone);
+
+    let half_view = projection.area.half_size();
+    let bounds = grid.bounds();
+    target.x = clamp_to_bounds(target.x, half_view.x, bounds.min.x, bounds.max.x);
+    target.y = clamp_to_bounds(target.y, half_view.y, bounds.min.y, bounds.max.y);
+
+    let new_position = match follow.smoothing {
+        Some(smoothing) => camera.lerp(target, 1. - (-smoothing * time.delta_seconds()).exp()),
+        None => target,
+    };
 
-    camera_transform.translation.x = player_transform.translation.x;
-    camera_transform.translation.y = player_transform.translation.y;
+    camera_transform.translation.x = new_position.x;
+    camera_transform.translation.y = new_position.y;
+}
+
+/// Keeps the camera from showing anything beyond the edge of the map, or
+/// centres the map on screen if it's too small to fill it.
+fn clamp_to_bounds(position: f32, half_view: f32, min: f32, max: f32) -> f32 {
+    if max - min <= 2. * half_view {
+        (min + max) / 2.
+    } else {
+        position.clamp(min + half_view, max - half_view)
+    }
 }
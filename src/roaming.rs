@@ -0,0 +1,142 @@
+use bevy::prelude::*;
+
+use crate::{ascii, combat, fadeout, graphics, player::Player, tilemap, GameState, TILE_SIZE};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (chase, touch_player)
+                .chain()
+                .run_if(in_state(GameState::Overworld)),
+        )
+        .add_systems(OnEnter(combat::State::Reward), remove_defeated);
+    }
+}
+
+/// Tiles per second.
+const SPEED: f32 = 1.5;
+const SIZE: f32 = TILE_SIZE * 0.8;
+/// How long an enemy the player ran away from waits before chasing them
+/// again, in seconds.
+const RECOVERY: f32 = 3.;
+
+/// An enemy visible on the overworld, which starts a fight when it touches the
+/// player.
+#[derive(Component)]
+pub struct Roamer {
+    typ: combat::EnemyType,
+    /// How close, in tiles, the player has to be before this enemy gives chase.
+    sight: f32,
+    recovery: Timer,
+}
+
+pub fn spawn(
+    commands: &mut Commands,
+    characters: &graphics::CharacterSheet,
+    typ: combat::EnemyType,
+    sight: f32,
+    tile: IVec2,
+) -> Entity {
+    let sprite = graphics::spawn_enemy(
+        commands,
+        &typ,
+        characters,
+        tilemap::tile_center(tile, 850.),
+        TILE_SIZE,
+    );
+
+    commands
+        .entity(sprite)
+        .insert(Name::new("Roaming Enemy"))
+        .insert(Roamer {
+            typ,
+            sight,
+            recovery: Timer::from_seconds(0., TimerMode::Once),
+        })
+        .id()
+}
+
+fn chase(
+    mut roamer_query: Query<(&mut Roamer, &mut Transform), Without<Player>>,
+    player_query: Query<(&Player, &Transform)>,
+    grid: Res<tilemap::Grid>,
+    time: Res<Time>,
+) {
+    let (player, player_transform) = player_query.single();
+
+    if !player.active {
+        return;
+    }
+
+    for (mut roamer, mut transform) in roamer_query.iter_mut() {
+        roamer.recovery.tick(time.delta());
+        if !roamer.recovery.finished() {
+            continue;
+        }
+
+        let offset = (player_transform.translation - transform.translation).truncate();
+        if offset.length() > roamer.sight * TILE_SIZE {
+            continue;
+        }
+
+        let movement = offset.normalize_or_zero() * SPEED * TILE_SIZE * time.delta_seconds();
+        // move along each axis separately so enemies slide along walls rather
+        // than sticking to them, the same as the player.
+        for delta in [Vec3::new(0., movement.y, 0.), Vec3::new(movement.x, 0., 0.)] {
+            let target = transform.translation + delta;
+            if !grid
+                .overlapping(target, SIZE)
+                .any(|cell| cell.blocks_movement())
+            {
+                transform.translation = target;
+            }
+        }
+    }
+}
+
+fn touch_player(
+    mut commands: Commands,
+    mut roamer_query: Query<(Entity, &mut Roamer, &Transform), Without<Player>>,
+    mut player_query: Query<(&mut Player, &Transform)>,
+    mut encounter: ResMut<combat::Encounter>,
+    ascii: Res<ascii::Sheet>,
+) {
+    let (mut player, player_transform) = player_query.single_mut();
+
+    if !player.active {
+        return;
+    }
+
+    for (entity, mut roamer, transform) in roamer_query.iter_mut() {
+        if !roamer.recovery.finished() {
+            continue;
+        }
+
+        let distance = transform
+            .translation
+            .truncate()
+            .distance(player_transform.translation.truncate());
+        if distance < SIZE {
+            // if the player gets away, give them a head start before this
+            // enemy starts chasing again.
+            roamer.recovery = Timer::from_seconds(RECOVERY, TimerMode::Once);
+            player.active = false;
+            *encounter = combat::Encounter {
+                enemy: Some(roamer.typ),
+                source: Some(entity),
+                boss: None,
+            };
+            fadeout::create(&mut commands, GameState::Combat, &ascii);
+            return;
+        }
+    }
+}
+
+fn remove_defeated(mut commands: Commands, encounter: Res<combat::Encounter>) {
+    if let Some(source) = encounter.source {
+        commands.entity(source).despawn_recursive();
+    }
+}
@@ -1,6 +1,10 @@
 use bevy::prelude::*;
 
-use crate::{ascii, fadeout, GameState};
+use crate::{
+    ascii, fadeout,
+    input::{Action, Actions},
+    GameState,
+};
 
 pub struct Plugin;
 
@@ -63,26 +67,25 @@ fn load(mut commands: Commands, assets: Res<AssetServer>) {
     commands.insert_resource(ui_assets);
 }
 
+/// Starts the game when the button is clicked, or when confirm is pressed so
+/// that it can be started without a mouse.
 fn start_button(
     mut commands: Commands,
-    entity_query: Query<(Entity, &ButtonActive, &Interaction), Changed<Interaction>>,
-    mut image_query: Query<&mut UiImage>,
+    mut entity_query: Query<(Entity, Ref<Interaction>, &mut UiImage), With<ButtonActive>>,
     ui_assets: Res<UiAssets>,
+    actions: Res<Actions>,
     ascii: Res<ascii::Sheet>,
 ) {
-    if let Ok((entity, _, interaction)) = entity_query.get_single() {
-        let mut image = image_query.get_mut(entity).unwrap();
+    let Ok((entity, interaction, mut image)) = entity_query.get_single_mut() else {
+        return;
+    };
 
-        match interaction {
-            Interaction::Pressed => {
-                *image = ui_assets.button_pressed.clone().into();
-                commands.entity(entity).remove::<ButtonActive>();
-                fadeout::create(&mut commands, GameState::Overworld, &ascii);
-            }
-            Interaction::Hovered | Interaction::None => {
-                *image = ui_assets.button.clone().into();
-            }
-        }
+    if *interaction == Interaction::Pressed || actions.just_pressed(Action::Confirm) {
+        *image = ui_assets.button_pressed.clone().into();
+        commands.entity(entity).remove::<ButtonActive>();
+        fadeout::create(&mut commands, GameState::Overworld, &ascii);
+    } else if interaction.is_changed() {
+        *image = ui_assets.button.clone().into();
     }
 }
 
@@ -0,0 +1,23 @@
+use bevy::{prelude::*, utils::HashSet};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Flags>();
+    }
+}
+
+/// Named milestones the player has reached, e.g. defeating a boss.
+#[derive(Resource, Default)]
+pub struct Flags(HashSet<String>);
+
+impl Flags {
+    pub fn set(&mut self, flag: &str) {
+        self.0.insert(flag.to_string());
+    }
+
+    pub fn is_set(&self, flag: &str) -> bool {
+        self.0.contains(flag)
+    }
+}
@@ -1,12 +1,17 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-};
+use std::collections::VecDeque;
 
-use bevy::prelude::*;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+    utils::{BoxedFuture, HashMap, HashSet},
+};
+use serde::Deserialize;
 
 use crate::{
-    ascii, npc,
+    ascii, audio, combat, graphics, npc,
+    player::{self, EncounterMode},
+    roaming,
     util::{hide, show},
     GameState, TILE_SIZE,
 };
@@ -15,71 +20,862 @@ pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            OnEnter(GameState::Overworld),
-            create_simple.run_if(run_once()),
+        app.add_asset::<TileMap>()
+            .init_asset_loader::<Loader>()
+            .init_resource::<Grid>()
+            .add_systems(Startup, load)
+            .add_systems(
+                Update,
+                (
+                    build,
+                    respawn_roamers.run_if(resource_changed::<EncounterMode>()),
+                    report_load_failure,
+                ),
+            )
+            .add_systems(
+                Update,
+                stream_chunks
+                    .after(build)
+                    .after(player::camera_follow)
+                    .run_if(in_state(GameState::Overworld)),
+            )
+            .add_systems(OnEnter(GameState::Overworld), (show::<Map>, play_music))
+            .add_systems(OnExit(GameState::Overworld), hide::<Map>);
+    }
+}
+
+/// The parsed contents of a `.map` file.
+#[derive(TypeUuid, TypePath)]
+#[uuid = "6b1c6f0e-3a47-4b8e-9d0a-5f2e8c1d7a93"]
+pub struct TileMap {
+    rows: Vec<Vec<char>>,
+    npcs: Vec<NpcSpawn>,
+    enemies: Vec<EnemySpawn>,
+    bosses: Vec<BossSpawn>,
+    pickups: Vec<PickupSpawn>,
+    encounter_rates: HashMap<char, f32>,
+    safe_zones: Vec<SafeZone>,
+    music: Option<String>,
+}
+
+/// The on-disk format of a `.map` file, which is RON with the tiles written
+/// out as a multi-line string, one row of tile characters per line.
+///
+/// Files that are just the rows of tiles, as maps used to be, still load with
+/// the defaults for everything else. In either format, an `@` tile is a
+/// stationary healer standing on the floor.
+#[derive(Deserialize)]
+struct Source {
+    tiles: String,
+    #[serde(default)]
+    npcs: Vec<NpcSpawn>,
+    /// Enemies that roam the map when using `player::EncounterMode::Visible`.
+    #[serde(default)]
+    enemies: Vec<EnemySpawn>,
+    #[serde(default)]
+    bosses: Vec<BossSpawn>,
+    #[serde(default)]
+    pickups: Vec<PickupSpawn>,
+    /// The chance of a random encounter for each step taken on a tile, by the
+    /// tile's character.
+    #[serde(default = "Source::default_encounter_rates")]
+    encounter_rates: HashMap<char, f32>,
+    #[serde(default)]
+    safe_zones: Vec<SafeZone>,
+    /// The id of the music played on the map, from the audio manifest.
+    #[serde(default)]
+    music: Option<String>,
+}
+
+impl Source {
+    fn plain(tiles: &str) -> Self {
+        Source {
+            tiles: tiles.to_string(),
+            npcs: Vec::new(),
+            enemies: Vec::new(),
+            bosses: Vec::new(),
+            pickups: Vec::new(),
+            encounter_rates: Source::default_encounter_rates(),
+            safe_zones: Vec::new(),
+            music: None,
+        }
+    }
+
+    fn default_encounter_rates() -> HashMap<char, f32> {
+        let mut rates = HashMap::new();
+        rates.insert('~', 0.1);
+        rates
+    }
+}
+
+/// Something lying on the map, which the player picks up by walking onto it.
+#[derive(Component, Clone, Copy, Deserialize)]
+pub enum Pickup {
+    /// Stops random encounters for the next `steps` steps.
+    Repel { steps: u32 },
+}
+
+#[derive(Deserialize)]
+struct PickupSpawn {
+    pickup: Pickup,
+    tile: (i32, i32),
+}
+
+/// An area of the map, including both corners, where there are never any
+/// random encounters.
+#[derive(Deserialize)]
+struct SafeZone {
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+impl SafeZone {
+    fn contains(&self, tile: IVec2) -> bool {
+        tile.cmpge(IVec2::from(self.min)).all() && tile.cmple(IVec2::from(self.max)).all()
+    }
+}
+
+#[derive(Deserialize)]
+struct NpcSpawn {
+    role: npc::Role,
+    tile: (i32, i32),
+    #[serde(default)]
+    behaviour: npc::Behaviour,
+}
+
+#[derive(Deserialize)]
+struct EnemySpawn {
+    typ: combat::EnemyType,
+    tile: (i32, i32),
+    #[serde(default = "EnemySpawn::default_sight")]
+    sight: f32,
+}
+
+impl EnemySpawn {
+    fn default_sight() -> f32 {
+        4.
+    }
+}
+
+/// A boss waiting on the map, which is fought after talking to it.
+#[derive(Deserialize)]
+struct BossSpawn {
+    /// The path of the boss' `.boss` file.
+    data: String,
+    tile: (i32, i32),
+}
+
+impl TileMap {
+    fn music_command(&self) -> audio::MusicCommand {
+        match &self.music {
+            Some(music) => audio::MusicCommand::Play(music.clone()),
+            None => audio::MusicCommand::Stop,
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, bevy::asset::Error> {
+        let text = std::str::from_utf8(bytes)?;
+        let mut source: Source = if text.trim_start().starts_with('(') {
+            ron::de::from_str(text)?
+        } else {
+            Source::plain(text)
+        };
+        // the tiles usually start on the line after the opening quote.
+        let tiles = source.tiles.strip_prefix('\n').unwrap_or(&source.tiles);
+        let mut rows: Vec<Vec<char>> = tiles
+            .lines()
+            .map(|line| line.trim_end().chars().collect())
+            .collect();
+
+        if rows.iter().all(|row| row.is_empty()) {
+            return Err(ParseError::Empty.into());
+        }
+
+        for (y, row) in rows.iter_mut().enumerate() {
+            for (x, char) in row.iter_mut().enumerate() {
+                // the ascii sheet only has glyphs for the first 256 code points.
+                if *char as usize > 255 {
+                    return Err(ParseError::UnsupportedTile { char: *char, x, y }.into());
+                }
+                if *char == '@' {
+                    *char = '.';
+                    source.npcs.push(NpcSpawn {
+                        role: npc::Role::Healer,
+                        tile: (x as i32, y as i32),
+                        behaviour: npc::Behaviour::Stationary,
+                    });
+                }
+            }
+        }
+
+        let map = TileMap {
+            rows,
+            npcs: source.npcs,
+            enemies: source.enemies,
+            bosses: source.bosses,
+            pickups: source.pickups,
+            encounter_rates: source.encounter_rates,
+            safe_zones: source.safe_zones,
+            music: source.music,
+        };
+
+        let spawns = map.npcs.iter().map(|npc| npc.tile);
+        let spawns = spawns.chain(map.enemies.iter().map(|enemy| enemy.tile));
+        let spawns = spawns.chain(map.bosses.iter().map(|boss| boss.tile));
+        let spawns = spawns.chain(map.pickups.iter().map(|pickup| pickup.tile));
+        for (x, y) in spawns {
+            if map.get(IVec2::new(x, y)).is_none() {
+                return Err(ParseError::SpawnOutOfBounds { x, y }.into());
+            }
+        }
+
+        for npc in map.npcs.iter() {
+            let npc::Behaviour::Patrol { waypoints } = &npc.behaviour else {
+                continue;
+            };
+            for (x, y) in waypoints.iter().copied() {
+                if matches!(map.get(IVec2::new(x, y)), None | Some('#')) {
+                    return Err(ParseError::UnreachableWaypoint { x, y }.into());
+                }
+            }
+        }
+
+        Ok(map)
+    }
+
+    pub fn width(&self) -> usize {
+        self.rows.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn get(&self, tile: IVec2) -> Option<char> {
+        let x = usize::try_from(tile.x).ok()?;
+        let y = usize::try_from(tile.y).ok()?;
+        self.rows.get(y)?.get(x).copied()
+    }
+}
+
+#[derive(Debug)]
+enum ParseError {
+    Empty,
+    UnsupportedTile { char: char, x: usize, y: usize },
+    SpawnOutOfBounds { x: i32, y: i32 },
+    UnreachableWaypoint { x: i32, y: i32 },
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "map contains no tiles"),
+            ParseError::UnsupportedTile { char, x, y } => {
+                write!(f, "unsupported tile {char:?} at ({x}, {y})")
+            }
+            ParseError::SpawnOutOfBounds { x, y } => {
+                write!(f, "spawn at ({x}, {y}) is outside the map")
+            }
+            ParseError::UnreachableWaypoint { x, y } => {
+                write!(f, "waypoint at ({x}, {y}) is outside the map or in a wall")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Default)]
+struct Loader;
+
+impl AssetLoader for Loader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let map = TileMap::parse(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map"]
+    }
+}
+
+/// The map currently shown in the overworld.
+#[derive(Resource)]
+pub struct CurrentMap(pub Handle<TileMap>);
+
+/// Tiles are spawned in square chunks of this many tiles a side, and only the
+/// chunks around the camera exist at any one time.
+const CHUNK_SIZE: i32 = 16;
+
+#[derive(Component, Default)]
+struct Map {
+    chunks: HashMap<IVec2, Entity>,
+}
+
+/// What occupies a single tile of the map.
+#[derive(Default, Clone, Copy)]
+pub struct Cell {
+    pub collider: bool,
+    /// The chance of a random encounter for each step taken on this tile.
+    pub encounter_rate: f32,
+    pub npc: Option<Entity>,
+}
+
+impl Cell {
+    /// Whether characters are prevented from walking onto this tile.
+    pub fn blocks_movement(&self) -> bool {
+        self.collider || self.npc.is_some()
+    }
+}
+
+/// A spatial index over the current map, so systems can look up what's on a
+/// tile without iterating every tile entity. Tile `(x, y)` is the `x`th
+/// character of the `y`th line of the map file.
+#[derive(Resource, Default)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+impl Grid {
+    fn new(width: usize, height: usize) -> Self {
+        Grid {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+        }
+    }
+
+    /// The area of the world covered by the map's tiles.
+    pub fn bounds(&self) -> Rect {
+        let half_tile = TILE_SIZE / 2.;
+        Rect::new(
+            -half_tile,
+            half_tile,
+            self.width as f32 * TILE_SIZE - half_tile,
+            -(self.height as f32 * TILE_SIZE) + half_tile,
         )
-        .add_systems(OnEnter(GameState::Overworld), show::<Map>)
-        .add_systems(OnExit(GameState::Overworld), hide::<Map>);
+    }
+
+    fn index(&self, tile: IVec2) -> Option<usize> {
+        let in_bounds = tile.x >= 0
+            && tile.y >= 0
+            && (tile.x as usize) < self.width
+            && (tile.y as usize) < self.height;
+        in_bounds.then(|| tile.y as usize * self.width + tile.x as usize)
+    }
+
+    pub fn get(&self, tile: IVec2) -> Option<&Cell> {
+        self.index(tile).map(|i| &self.cells[i])
+    }
+
+    fn get_mut(&mut self, tile: IVec2) -> Option<&mut Cell> {
+        self.index(tile).map(|i| &mut self.cells[i])
+    }
+
+    /// Whether characters can't step onto `tile`, including when it's off the
+    /// edge of the map.
+    pub fn blocks_movement(&self, tile: IVec2) -> bool {
+        match self.get(tile) {
+            Some(cell) => cell.blocks_movement(),
+            None => true,
+        }
+    }
+
+    /// The first tile to step onto when walking from `from` to `to` around any
+    /// walls, or `None` if there's no way through. Characters in the way are
+    /// ignored, since they're usually gone by the time they're reached.
+    pub fn first_step(&self, from: IVec2, to: IVec2) -> Option<IVec2> {
+        // searches outwards from `to`, so whichever tile a tile was reached
+        // from is the next step back towards `to`.
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(to);
+        queue.push_back(to);
+        while let Some(tile) = queue.pop_front() {
+            for offset in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                let neighbour = tile + offset;
+                if neighbour == from {
+                    return Some(tile);
+                }
+                let walkable = self.get(neighbour).is_some_and(|cell| !cell.collider);
+                if walkable && visited.insert(neighbour) {
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        None
+    }
+
+    /// Records an npc as occupying `tile`, as well as any tile it already
+    /// occupies, e.g. while it steps from one to the other.
+    pub fn reserve_npc(&mut self, npc: Entity, tile: IVec2) {
+        if let Some(cell) = self.get_mut(tile) {
+            cell.npc = Some(npc);
+        }
+    }
+
+    /// Removes an npc from the grid, e.g. once it's been despawned.
+    pub fn clear_npc(&mut self, npc: Entity, tile: IVec2) {
+        if let Some(cell) = self.get_mut(tile) {
+            if cell.npc == Some(npc) {
+                cell.npc = None;
+            }
+        }
+    }
+
+    /// The cells overlapped by a square of side `size` centred on `translation`.
+    pub fn overlapping(&self, translation: Vec3, size: f32) -> impl Iterator<Item = &Cell> {
+        overlapped_tiles(translation, size).filter_map(|tile| self.get(tile))
+    }
+}
+
+/// The tiles overlapped by a square of side `size` centred on `translation`.
+pub fn overlapped_tiles(translation: Vec3, size: f32) -> impl Iterator<Item = IVec2> {
+    let half = size / 2.;
+    let min = tile_at(translation - Vec3::new(half, -half, 0.));
+    let max = tile_at(translation + Vec3::new(half, -half, 0.));
+    (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+}
+
+fn chunk_of(tile: IVec2) -> IVec2 {
+    IVec2::new(tile.x.div_euclid(CHUNK_SIZE), tile.y.div_euclid(CHUNK_SIZE))
+}
+
+/// The world position of the centre of a tile.
+pub fn tile_center(tile: IVec2, z: f32) -> Vec3 {
+    Vec3::new(tile.x as f32 * TILE_SIZE, -(tile.y as f32) * TILE_SIZE, z)
+}
+
+/// The tile containing the given world position.
+pub fn tile_at(translation: Vec3) -> IVec2 {
+    IVec2::new(
+        (translation.x / TILE_SIZE).round() as i32,
+        (-translation.y / TILE_SIZE).round() as i32,
+    )
+}
+
+fn load(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.insert_resource(CurrentMap(assets.load("overworld.map")));
+}
+
+fn report_load_failure(
+    current: Res<CurrentMap>,
+    assets: Res<AssetServer>,
+    mut reported: Local<bool>,
+) {
+    match assets.get_load_state(&current.0) {
+        LoadState::Failed if !*reported => {
+            warn!("Couldn't load the overworld map, see above for details");
+            *reported = true;
+        }
+        LoadState::Failed => (),
+        _ => *reported = false,
+    }
+}
+
+/// (Re)builds the tilemap whenever the current map finishes loading, which
+/// includes every time the file is edited while hot reloading is enabled.
+#[allow(clippy::too_many_arguments)]
+fn build(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<TileMap>>,
+    maps: Res<Assets<TileMap>>,
+    current: Res<CurrentMap>,
+    mut existing_query: Query<(Entity, Option<&Children>, &mut Map)>,
+    game_state: Res<State<GameState>>,
+    ascii: Res<ascii::Sheet>,
+    characters: Res<graphics::CharacterSheet>,
+    encounter_mode: Res<EncounterMode>,
+    asset_server: Res<AssetServer>,
+    mut music_events: EventWriter<audio::MusicCommand>,
+) {
+    let changed = events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle == &current.0,
+        AssetEvent::Removed { .. } => false,
+    });
+    if !changed {
+        return;
+    }
+
+    let Some(map) = maps.get(&current.0) else {
+        return;
+    };
+
+    // the map entity is kept across reloads (only its contents are replaced)
+    // so that `stream_chunks` can safely add chunks to it in the same frame.
+    let map_entity = match existing_query.get_single_mut() {
+        Ok((entity, children, mut existing)) => {
+            existing.chunks.clear();
+            for child in children.into_iter().flatten() {
+                commands.entity(*child).despawn_recursive();
+            }
+            entity
+        }
+        Err(_) => {
+            let visibility = if game_state.get() == &GameState::Overworld {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+            commands
+                .spawn_empty()
+                .insert(Name::new("Map"))
+                .insert(SpatialBundle {
+                    visibility,
+                    ..Default::default()
+                })
+                .insert(Map::default())
+                .id()
+        }
+    };
+
+    let grid = spawn(
+        &mut commands,
+        &asset_server,
+        &ascii,
+        &characters,
+        map,
+        map_entity,
+        *encounter_mode,
+    );
+    commands.insert_resource(grid);
+
+    if game_state.get() == &GameState::Overworld {
+        music_events.send(map.music_command());
     }
 }
 
-#[derive(Component)]
-struct Map;
+/// Plays the current map's music whenever the overworld is shown. Maps that
+/// haven't loaded yet play theirs once they're built.
+fn play_music(
+    maps: Res<Assets<TileMap>>,
+    current: Res<CurrentMap>,
+    mut music_events: EventWriter<audio::MusicCommand>,
+) {
+    if let Some(map) = maps.get(&current.0) {
+        music_events.send(map.music_command());
+    }
+}
 
-#[derive(Component)]
-pub struct EncounterSpawner;
+fn spawn(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    ascii: &ascii::Sheet,
+    characters: &graphics::CharacterSheet,
+    map: &TileMap,
+    map_entity: Entity,
+    encounter_mode: EncounterMode,
+) -> Grid {
+    let mut grid = Grid::new(map.width(), map.height());
 
-#[derive(Component)]
-pub struct Collider;
+    for (y, row) in map.rows.iter().enumerate() {
+        for (x, char) in row.iter().copied().enumerate() {
+            let tile = IVec2::new(x as i32, y as i32);
+            let cell = grid
+                .get_mut(tile)
+                .expect("the grid is sized to fit every row");
+            if char == '#' {
+                cell.collider = true;
+            }
+            if !map.safe_zones.iter().any(|zone| zone.contains(tile)) {
+                cell.encounter_rate = map.encounter_rates.get(&char).copied().unwrap_or(0.);
+            }
+        }
+    }
 
-fn create_simple(mut commands: Commands, ascii: Res<ascii::Sheet>) {
-    let file = File::open("assets/map.txt").expect("No map file found");
+    // npcs aren't part of any chunk, so that they keep moving (and the grid's
+    // references to them stay valid) when the chunk around them is despawned.
+    let mut npcs = Vec::new();
+    for spawn in map.npcs.iter() {
+        let tile = IVec2::new(spawn.tile.0, spawn.tile.1);
+        let npc = npc::spawn(
+            commands,
+            characters,
+            spawn.role,
+            spawn.behaviour.clone(),
+            tile,
+        );
+        if let Some(cell) = grid.get_mut(tile) {
+            cell.npc = Some(npc);
+        }
+        npcs.push(npc);
+    }
+
+    for spawn in map.bosses.iter() {
+        let tile = IVec2::new(spawn.tile.0, spawn.tile.1);
+        let boss = npc::spawn_boss(commands, asset_server.load(&spawn.data), tile);
+        if let Some(cell) = grid.get_mut(tile) {
+            cell.npc = Some(boss);
+        }
+        npcs.push(boss);
+    }
+
+    commands.entity(map_entity).push_children(&npcs);
+
+    // like npcs, pickups aren't part of any chunk so that streaming chunks
+    // back in doesn't bring back ones that have been picked up.
+    let pickups: Vec<_> = map
+        .pickups
+        .iter()
+        .map(|spawn| {
+            let tile = IVec2::new(spawn.tile.0, spawn.tile.1);
+            let pickup = ascii::spawn_sprite(
+                commands,
+                ascii,
+                '*' as usize,
+                Color::rgb(0.9, 0.8, 0.2),
+                tile_center(tile, 700.),
+                Vec3::splat(1.),
+            );
+            commands
+                .entity(pickup)
+                .insert(Name::new("Pickup"))
+                .insert(spawn.pickup);
+            pickup
+        })
+        .collect();
+    commands.entity(map_entity).push_children(&pickups);
+
+    if encounter_mode == EncounterMode::Visible {
+        spawn_roamers(commands, characters, map, map_entity);
+    }
+
+    grid
+}
+
+fn spawn_roamers(
+    commands: &mut Commands,
+    characters: &graphics::CharacterSheet,
+    map: &TileMap,
+    map_entity: Entity,
+) {
+    let enemies: Vec<_> = map
+        .enemies
+        .iter()
+        .map(|spawn| {
+            let tile = IVec2::new(spawn.tile.0, spawn.tile.1);
+            roaming::spawn(commands, characters, spawn.typ, spawn.sight, tile)
+        })
+        .collect();
+    commands.entity(map_entity).push_children(&enemies);
+}
+
+/// Swaps the map's roaming enemies in or out when the encounter mode is
+/// changed, leaving the rest of the map as it is.
+fn respawn_roamers(
+    mut commands: Commands,
+    maps: Res<Assets<TileMap>>,
+    current: Res<CurrentMap>,
+    map_query: Query<Entity, With<Map>>,
+    roamer_query: Query<Entity, With<roaming::Roamer>>,
+    characters: Res<graphics::CharacterSheet>,
+    encounter_mode: Res<EncounterMode>,
+) {
+    let (Some(map), Ok(map_entity)) = (maps.get(&current.0), map_query.get_single()) else {
+        // `build` spawns them with the rest of the map once it's loaded.
+        return;
+    };
+    for roamer in roamer_query.iter() {
+        commands.entity(roamer).despawn_recursive();
+    }
+    if *encounter_mode == EncounterMode::Visible {
+        spawn_roamers(&mut commands, &characters, map, map_entity);
+    }
+}
+
+fn spawn_tile(commands: &mut Commands, ascii: &ascii::Sheet, tile: IVec2, char: char) -> Entity {
+    let color = match char {
+        '#' => Color::rgb(0.7, 0.7, 0.7),
+        '~' => Color::rgb(0.2, 0.9, 0.2),
+        _ => Color::rgb(0.9, 0.9, 0.9),
+    };
+    ascii::spawn_sprite(
+        commands,
+        ascii,
+        char as usize,
+        color,
+        tile_center(tile, 100.),
+        Vec3::splat(1.),
+    )
+}
+
+fn spawn_chunk(
+    commands: &mut Commands,
+    ascii: &ascii::Sheet,
+    map: &TileMap,
+    chunk: IVec2,
+) -> Entity {
     let mut tiles = Vec::new();
+    let origin = chunk * CHUNK_SIZE;
 
-    for (y, line) in BufReader::new(file).lines().enumerate() {
-        if let Ok(line) = line {
-            for (x, char) in line.chars().enumerate() {
-                let color = match char {
-                    '#' => Color::rgb(0.7, 0.7, 0.7),
-                    '@' => Color::rgb(0.5, 0.5, 0.2),
-                    '~' => Color::rgb(0.2, 0.9, 0.2),
-                    _ => Color::rgb(0.9, 0.9, 0.9),
-                };
-                let tile = ascii::spawn_sprite(
-                    &mut commands,
-                    &ascii,
-                    char as usize,
-                    color,
-                    Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 100.),
-                    Vec3::splat(1.),
-                );
-                match char {
-                    '#' => {
-                        commands.entity(tile).insert(Collider);
-                    }
-                    '@' => {
-                        commands
-                            .entity(tile)
-                            .insert(Collider)
-                            .insert(npc::Role::Healer);
-                    }
-                    '~' => {
-                        commands.entity(tile).insert(EncounterSpawner);
-                    }
-                    _ => (),
-                };
-                tiles.push(tile);
+    for y in origin.y..origin.y + CHUNK_SIZE {
+        for x in origin.x..origin.x + CHUNK_SIZE {
+            let tile = IVec2::new(x, y);
+            if let Some(char) = map.get(tile) {
+                tiles.push(spawn_tile(commands, ascii, tile, char));
             }
         }
     }
 
     commands
         .spawn_empty()
-        .insert(Name::new("Map"))
+        .insert(Name::new(format!("Chunk ({}, {})", chunk.x, chunk.y)))
         .insert(SpatialBundle::default())
-        .insert(Transform::default())
-        .insert(GlobalTransform::default())
-        .insert(Map)
-        .push_children(&tiles);
+        .push_children(&tiles)
+        .id()
+}
+
+/// Spawns the chunks in and around the camera's view, and despawns any that
+/// have scrolled out of it.
+fn stream_chunks(
+    mut commands: Commands,
+    mut map_query: Query<(Entity, &mut Map)>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    maps: Res<Assets<TileMap>>,
+    current: Res<CurrentMap>,
+    ascii: Res<ascii::Sheet>,
+) {
+    let Ok((map_entity, mut map)) = map_query.get_single_mut() else {
+        return;
+    };
+    let Some(tiles) = maps.get(&current.0) else {
+        return;
+    };
+    let (camera_transform, projection) = camera_query.single();
+
+    let corner_a = chunk_of(tile_at(
+        camera_transform.translation + projection.area.min.extend(0.),
+    ));
+    let corner_b = chunk_of(tile_at(
+        camera_transform.translation + projection.area.max.extend(0.),
+    ));
+    let last_chunk = chunk_of(IVec2::new(
+        tiles.width() as i32 - 1,
+        tiles.height() as i32 - 1,
+    ));
+    // keep a margin of one chunk around the view so tiles are spawned before
+    // they scroll on screen.
+    let min = (corner_a.min(corner_b) - IVec2::ONE).max(IVec2::ZERO);
+    let max = (corner_a.max(corner_b) + IVec2::ONE).min(last_chunk);
+
+    map.chunks.retain(|chunk, entity| {
+        let visible = chunk.cmpge(min).all() && chunk.cmple(max).all();
+        if !visible {
+            commands.entity(*entity).despawn_recursive();
+        }
+        visible
+    });
+
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let chunk = IVec2::new(x, y);
+            if !map.chunks.contains_key(&chunk) {
+                let entity = spawn_chunk(&mut commands, &ascii, tiles, chunk);
+                commands.entity(map_entity).add_child(entity);
+                map.chunks.insert(chunk, entity);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> ParseError {
+        let error = TileMap::parse(text.as_bytes())
+            .err()
+            .expect("the map should fail");
+        error.downcast().expect("a parse error")
+    }
+
+    fn grid(rows: &[&str]) -> Grid {
+        let mut grid = Grid::new(rows[0].len(), rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, char) in row.chars().enumerate() {
+                let tile = IVec2::new(x as i32, y as i32);
+                grid.get_mut(tile).unwrap().collider = char == '#';
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn plain_text_maps_spawn_healers_on_at_tiles() {
+        let map = TileMap::parse(b"####\n#.@#\n####\n").unwrap();
+        assert_eq!(map.height(), 3);
+        assert_eq!(map.width(), 4);
+        assert_eq!(map.get(IVec2::new(2, 1)), Some('.'));
+        assert_eq!(map.npcs.len(), 1);
+        assert!(map.npcs[0].role == npc::Role::Healer);
+        assert_eq!(map.npcs[0].tile, (2, 1));
+        assert!(matches!(map.npcs[0].behaviour, npc::Behaviour::Stationary));
+        assert!(map.music.is_none());
+    }
+
+    #[test]
+    fn spawns_outside_the_map_are_rejected() {
+        let error = parse_error(
+            r##"(
+                tiles: "...\n...",
+                pickups: [(pickup: Repel(steps: 5), tile: (3, 0))],
+            )"##,
+        );
+        assert!(matches!(error, ParseError::SpawnOutOfBounds { x: 3, y: 0 }));
+    }
+
+    #[test]
+    fn waypoints_in_walls_are_rejected() {
+        let error = parse_error(
+            r##"(
+                tiles: "..#\n...",
+                npcs: [(
+                    role: Healer,
+                    tile: (0, 0),
+                    behaviour: Patrol(waypoints: [(0, 1), (2, 0)]),
+                )],
+            )"##,
+        );
+        assert!(matches!(
+            error,
+            ParseError::UnreachableWaypoint { x: 2, y: 0 }
+        ));
+    }
+
+    #[test]
+    fn first_step_heads_straight_for_the_target() {
+        let grid = grid(&["....."]);
+        let step = grid.first_step(IVec2::new(0, 0), IVec2::new(4, 0));
+        assert_eq!(step, Some(IVec2::new(1, 0)));
+    }
+
+    #[test]
+    fn first_step_walks_around_walls() {
+        let grid = grid(&[
+            ".#.", //
+            ".#.", //
+            "...",
+        ]);
+        let step = grid.first_step(IVec2::new(0, 0), IVec2::new(2, 0));
+        assert_eq!(step, Some(IVec2::new(0, 1)));
+    }
+
+    #[test]
+    fn first_step_gives_up_on_walled_off_targets() {
+        let grid = grid(&[
+            "..#.", //
+            "..#.", //
+            "..#.",
+        ]);
+        assert_eq!(grid.first_step(IVec2::new(0, 0), IVec2::new(3, 1)), None);
+    }
 }
@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
 
 pub fn show<T: Component>(mut query: Query<&mut Visibility, With<T>>) {
     if let Ok(mut visibility) = query.get_single_mut() {
@@ -11,3 +12,29 @@ pub fn hide<T: Component>(mut query: Query<&mut Visibility, With<T>>) {
         *visibility = Visibility::Hidden;
     }
 }
+
+/// Reads settings saved with `save_settings`, or the defaults if there aren't
+/// any yet.
+pub fn load_settings<T: DeserializeOwned + Default>(path: &str) -> T {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return T::default();
+    };
+    ron::from_str(&text).unwrap_or_else(|error| {
+        warn!("ignoring bad settings in {path}: {error}");
+        T::default()
+    })
+}
+
+pub fn save_settings<T: Serialize>(path: &str, settings: &T) {
+    let save = || -> Result<(), Box<dyn std::error::Error>> {
+        let text = ron::ser::to_string_pretty(settings, Default::default())?;
+        if let Some(directory) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    };
+    if let Err(error) = save() {
+        warn!("couldn't save settings to {path}: {error}");
+    }
+}
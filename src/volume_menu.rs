@@ -0,0 +1,125 @@
+use bevy::prelude::*;
+
+use crate::{
+    audio::{self, Mixer},
+    input::{Action, Actions},
+    menu,
+    player::Player,
+    GameState,
+};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(menu::Plugin::<VolumeOption>::default())
+            .add_systems(
+                Update,
+                (select, relabel.run_if(resource_changed::<Mixer>()))
+                    .chain()
+                    .run_if(in_state(GameState::Overworld)),
+            )
+            .add_systems(OnExit(GameState::Overworld), menu::close::<VolumeOption>);
+    }
+}
+
+/// How much selecting a channel turns it up by, before wrapping around to
+/// silent.
+const VOLUME_STEP: f32 = 0.1;
+
+#[derive(Clone, Copy)]
+enum VolumeOption {
+    Master,
+    Music,
+    Sfx,
+    Mute,
+    Back,
+}
+
+/// Spawns the volume menu with its top left corner at `translation`. The music
+/// should be playing, so that changes to its volume can be heard.
+pub fn spawn(commands: &mut Commands, translation: Vec3, mixer: &Mixer) {
+    commands
+        .spawn(SpatialBundle::from_transform(Transform::from_translation(
+            translation,
+        )))
+        .insert(Name::new("Volume Menu"))
+        .insert(menu::Menu::new(entries(mixer), menu::Layout::Column).closed_by(Action::Menu));
+}
+
+fn entries(mixer: &Mixer) -> Vec<menu::Entry<VolumeOption>> {
+    let percent = |volume: f32| format!("{:.0}%", volume * 100.);
+    vec![
+        menu::Entry::action(
+            format!("Master: {}", percent(mixer.master)),
+            VolumeOption::Master,
+        ),
+        menu::Entry::action(
+            format!("Music: {}", percent(mixer.music)),
+            VolumeOption::Music,
+        ),
+        menu::Entry::action(format!("Sfx: {}", percent(mixer.sfx)), VolumeOption::Sfx),
+        menu::Entry::action(
+            if mixer.muted { "Mute: On" } else { "Mute: Off" },
+            VolumeOption::Mute,
+        ),
+        menu::Entry::action("Back", VolumeOption::Back),
+    ]
+}
+
+/// Turns a volume up a step, or back down to nothing once it's at full.
+fn step(volume: f32) -> f32 {
+    if volume >= 1. - VOLUME_STEP / 2. {
+        0.
+    } else {
+        ((volume / VOLUME_STEP).round() + 1.) * VOLUME_STEP
+    }
+}
+
+fn select(
+    mut commands: Commands,
+    mut events: EventReader<menu::Event<VolumeOption>>,
+    mut player_query: Query<&mut Player>,
+    mut mixer: ResMut<Mixer>,
+    mut actions: ResMut<Actions>,
+    mut music_events: EventWriter<audio::MusicCommand>,
+) {
+    for event in events.iter() {
+        let menu = match event {
+            menu::Event::Selected { item, menu } => match item {
+                VolumeOption::Master => {
+                    mixer.master = step(mixer.master);
+                    continue;
+                }
+                VolumeOption::Music => {
+                    mixer.music = step(mixer.music);
+                    continue;
+                }
+                VolumeOption::Sfx => {
+                    mixer.sfx = step(mixer.sfx);
+                    continue;
+                }
+                VolumeOption::Mute => {
+                    mixer.muted = !mixer.muted;
+                    continue;
+                }
+                VolumeOption::Back => menu,
+            },
+            menu::Event::Cancelled { menu } => menu,
+        };
+
+        menu::resume_overworld(
+            &mut commands,
+            *menu,
+            &mut player_query.single_mut(),
+            &mut actions,
+            &mut music_events,
+        );
+    }
+}
+
+fn relabel(mut menu_query: Query<&mut menu::Menu<VolumeOption>>, mixer: Res<Mixer>) {
+    for mut menu in menu_query.iter_mut() {
+        menu.set_entries(entries(&mixer));
+    }
+}